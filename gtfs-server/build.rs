@@ -1,7 +1,42 @@
 use prost_build;
 use std::io::Result;
 
+/// Messages of gtfs-requests.proto sent by devices, which may omit fields when sent as JSON.
+const REQUESTS: [&str; 4] = [
+    ".gtfs_requests.DepartureRequest",
+    ".gtfs_requests.FieldMask",
+    ".gtfs_requests.ClosestVehicleRequest",
+    ".gtfs_requests.AlertsRequest",
+];
+
+/// Messages of gtfs-requests.proto sent by the server.
+const RESPONSES: [&str; 7] = [
+    ".gtfs_requests.DepartureResponse",
+    ".gtfs_requests.Departure",
+    ".gtfs_requests.ClosestVehicleResponse",
+    ".gtfs_requests.Vehicle",
+    ".gtfs_requests.AlertsResponse",
+    ".gtfs_requests.Alert",
+    ".gtfs_requests.Error",
+];
+
 fn main() -> Result<()> {
+    // gtfs-requests.proto is outside the package, so isn't watched by default
+    println!("cargo:rerun-if-changed=src/gtfs-realtime.proto");
+    println!("cargo:rerun-if-changed=../gtfs-requests.proto");
     prost_build::compile_protos(&["src/gtfs-realtime.proto"], &["src/"])?;
+
+    // the departure board protocol is also served as JSON
+    let mut config = prost_build::Config::new();
+    for message in REQUESTS.iter() {
+        config.type_attribute(
+            message,
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        );
+    }
+    for message in RESPONSES.iter() {
+        config.type_attribute(message, "#[derive(serde::Serialize, serde::Deserialize)]");
+    }
+    config.compile_protos(&["../gtfs-requests.proto"], &["../"])?;
     Ok(())
 }
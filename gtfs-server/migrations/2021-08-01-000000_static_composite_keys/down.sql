@@ -0,0 +1,9 @@
+DROP INDEX trips_service_id_idx;
+DROP INDEX trips_route_id_idx;
+DROP INDEX stop_times_stop_id_idx;
+
+ALTER TABLE stop_times DROP CONSTRAINT stop_times_pkey;
+ALTER TABLE stop_times ADD PRIMARY KEY (trip_id);
+
+ALTER TABLE calendar_dates DROP CONSTRAINT calendar_dates_pkey;
+ALTER TABLE calendar_dates ADD PRIMARY KEY (service_id);
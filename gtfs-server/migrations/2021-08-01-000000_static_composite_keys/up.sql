@@ -0,0 +1,9 @@
+ALTER TABLE calendar_dates DROP CONSTRAINT calendar_dates_pkey;
+ALTER TABLE calendar_dates ADD PRIMARY KEY (service_id, date);
+
+ALTER TABLE stop_times DROP CONSTRAINT stop_times_pkey;
+ALTER TABLE stop_times ADD PRIMARY KEY (trip_id, stop_sequence);
+
+CREATE INDEX stop_times_stop_id_idx ON stop_times (stop_id);
+CREATE INDEX trips_route_id_idx ON trips (route_id);
+CREATE INDEX trips_service_id_idx ON trips (service_id);
@@ -0,0 +1,7 @@
+DROP TABLE agency;
+DROP TABLE shapes;
+DROP TABLE frequencies;
+DROP TABLE transfers;
+DROP TABLE feed_info;
+DROP TABLE fare_attributes;
+DROP TABLE fare_rules;
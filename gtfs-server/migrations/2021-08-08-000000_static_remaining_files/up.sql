@@ -0,0 +1,70 @@
+CREATE TABLE agency (
+    agency_id       TEXT    NOT NULL PRIMARY KEY,
+    agency_name     TEXT    NOT NULL,
+    agency_url      TEXT    NOT NULL,
+    agency_timezone TEXT    NOT NULL,
+    agency_lang     TEXT,
+    agency_phone    TEXT,
+    agency_fare_url TEXT,
+    agency_email    TEXT
+);
+
+CREATE TABLE shapes (
+    shape_id            TEXT    NOT NULL,
+    shape_pt_lat        REAL    NOT NULL,
+    shape_pt_lon        REAL    NOT NULL,
+    shape_pt_sequence   INTEGER NOT NULL,
+    shape_dist_traveled REAL,
+    PRIMARY KEY (shape_id, shape_pt_sequence)
+);
+
+CREATE TABLE frequencies (
+    trip_id         TEXT    NOT NULL,
+    start_time      TEXT    NOT NULL,
+    end_time        TEXT    NOT NULL,
+    headway_secs    INTEGER NOT NULL,
+    exact_times     INTEGER NOT NULL,
+    PRIMARY KEY (trip_id, start_time)
+);
+
+CREATE TABLE transfers (
+    from_stop_id        INTEGER NOT NULL,
+    to_stop_id          INTEGER NOT NULL,
+    transfer_type       INTEGER NOT NULL,
+    min_transfer_time   INTEGER,
+    PRIMARY KEY (from_stop_id, to_stop_id)
+);
+
+CREATE TABLE feed_info (
+    feed_publisher_name TEXT    NOT NULL PRIMARY KEY,
+    feed_publisher_url  TEXT    NOT NULL,
+    feed_lang           TEXT    NOT NULL,
+    feed_start_date     INTEGER,
+    feed_end_date       INTEGER,
+    feed_version        TEXT,
+    feed_contact_email  TEXT,
+    feed_contact_url    TEXT
+);
+
+CREATE TABLE fare_attributes (
+    fare_id             TEXT    NOT NULL PRIMARY KEY,
+    price               REAL    NOT NULL,
+    currency_type       TEXT    NOT NULL,
+    payment_method      INTEGER NOT NULL,
+    transfers           INTEGER,
+    agency_id           TEXT,
+    transfer_duration   INTEGER
+);
+
+-- Unused fare rule columns are stored as empty strings so that every rule has a primary key.
+CREATE TABLE fare_rules (
+    fare_id         TEXT    NOT NULL,
+    route_id        TEXT    NOT NULL DEFAULT '',
+    origin_id       TEXT    NOT NULL DEFAULT '',
+    destination_id  TEXT    NOT NULL DEFAULT '',
+    contains_id     TEXT    NOT NULL DEFAULT '',
+    PRIMARY KEY (fare_id, route_id, origin_id, destination_id, contains_id)
+);
+
+CREATE INDEX shapes_shape_id_idx ON shapes (shape_id);
+CREATE INDEX frequencies_trip_id_idx ON frequencies (trip_id);
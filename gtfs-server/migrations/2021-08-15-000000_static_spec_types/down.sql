@@ -0,0 +1,46 @@
+-- Converting back is lossy: identifiers which are not integers cannot be restored, and
+-- non-timepoint stop times become midnight.
+CREATE FUNCTION gtfs_time_text(s INTEGER) RETURNS TEXT AS $$
+    SELECT lpad((s / 3600)::TEXT, 2, '0') || ':'
+        || lpad((s / 60 % 60)::TEXT, 2, '0') || ':'
+        || lpad((s % 60)::TEXT, 2, '0')
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE transfers
+    ALTER COLUMN from_stop_id TYPE INTEGER USING from_stop_id::INTEGER,
+    ALTER COLUMN to_stop_id   TYPE INTEGER USING to_stop_id::INTEGER;
+
+ALTER TABLE stops
+    ALTER COLUMN stop_id   TYPE INTEGER USING stop_id::INTEGER,
+    ALTER COLUMN stop_code TYPE INTEGER USING stop_code::INTEGER,
+    ALTER COLUMN zone_id   TYPE INTEGER USING zone_id::INTEGER;
+ALTER TABLE stops ADD CONSTRAINT stops_stop_code_key UNIQUE (stop_code);
+
+ALTER TABLE stop_times
+    ALTER COLUMN arrival_time   TYPE TEXT USING gtfs_time_text(COALESCE(arrival_time, 0)),
+    ALTER COLUMN arrival_time   SET NOT NULL,
+    ALTER COLUMN departure_time TYPE TEXT USING gtfs_time_text(COALESCE(departure_time, 0)),
+    ALTER COLUMN departure_time SET NOT NULL,
+    ALTER COLUMN stop_id        TYPE INTEGER USING stop_id::INTEGER;
+
+ALTER TABLE routes
+    ALTER COLUMN route_short_name TYPE INTEGER USING route_short_name::INTEGER,
+    ALTER COLUMN route_short_name SET NOT NULL,
+    ALTER COLUMN route_long_name  SET NOT NULL;
+
+ALTER TABLE frequencies
+    ALTER COLUMN start_time TYPE TEXT USING gtfs_time_text(start_time),
+    ALTER COLUMN end_time   TYPE TEXT USING gtfs_time_text(end_time);
+
+ALTER TABLE feed_info
+    ALTER COLUMN feed_start_date TYPE INTEGER USING to_char(feed_start_date, 'YYYYMMDD')::INTEGER,
+    ALTER COLUMN feed_end_date   TYPE INTEGER USING to_char(feed_end_date, 'YYYYMMDD')::INTEGER;
+
+ALTER TABLE calendar_dates
+    ALTER COLUMN date TYPE INTEGER USING to_char(date, 'YYYYMMDD')::INTEGER;
+
+ALTER TABLE calendar
+    ALTER COLUMN start_date TYPE INTEGER USING to_char(start_date, 'YYYYMMDD')::INTEGER,
+    ALTER COLUMN end_date   TYPE INTEGER USING to_char(end_date, 'YYYYMMDD')::INTEGER;
+
+DROP FUNCTION gtfs_time_text(INTEGER);
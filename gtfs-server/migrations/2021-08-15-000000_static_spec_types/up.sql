@@ -0,0 +1,51 @@
+-- Convert a GTFS time (H:MM:SS, possibly later than 24:00:00) into seconds since the start of
+-- the service day.
+CREATE FUNCTION gtfs_time_seconds(t TEXT) RETURNS INTEGER AS $$
+    SELECT split_part(t, ':', 1)::INTEGER * 3600
+         + split_part(t, ':', 2)::INTEGER * 60
+         + split_part(t, ':', 3)::INTEGER
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE FUNCTION gtfs_date(d INTEGER) RETURNS DATE AS $$
+    SELECT to_date(d::TEXT, 'YYYYMMDD')
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE calendar
+    ALTER COLUMN start_date TYPE DATE USING gtfs_date(start_date),
+    ALTER COLUMN end_date   TYPE DATE USING gtfs_date(end_date);
+
+ALTER TABLE calendar_dates
+    ALTER COLUMN date TYPE DATE USING gtfs_date(date);
+
+ALTER TABLE feed_info
+    ALTER COLUMN feed_start_date TYPE DATE USING gtfs_date(feed_start_date),
+    ALTER COLUMN feed_end_date   TYPE DATE USING gtfs_date(feed_end_date);
+
+ALTER TABLE frequencies
+    ALTER COLUMN start_time TYPE INTEGER USING gtfs_time_seconds(start_time),
+    ALTER COLUMN end_time   TYPE INTEGER USING gtfs_time_seconds(end_time);
+
+ALTER TABLE routes
+    ALTER COLUMN route_short_name TYPE TEXT,
+    ALTER COLUMN route_short_name DROP NOT NULL,
+    ALTER COLUMN route_long_name  DROP NOT NULL;
+
+ALTER TABLE stop_times
+    ALTER COLUMN arrival_time   TYPE INTEGER USING gtfs_time_seconds(arrival_time),
+    ALTER COLUMN arrival_time   DROP NOT NULL,
+    ALTER COLUMN departure_time TYPE INTEGER USING gtfs_time_seconds(departure_time),
+    ALTER COLUMN departure_time DROP NOT NULL,
+    ALTER COLUMN stop_id        TYPE TEXT;
+
+ALTER TABLE stops DROP CONSTRAINT stops_stop_code_key;
+ALTER TABLE stops
+    ALTER COLUMN stop_id   TYPE TEXT,
+    ALTER COLUMN stop_code TYPE TEXT,
+    ALTER COLUMN zone_id   TYPE TEXT;
+
+ALTER TABLE transfers
+    ALTER COLUMN from_stop_id TYPE TEXT,
+    ALTER COLUMN to_stop_id   TYPE TEXT;
+
+DROP FUNCTION gtfs_date(INTEGER);
+DROP FUNCTION gtfs_time_seconds(TEXT);
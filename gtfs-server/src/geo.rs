@@ -0,0 +1,349 @@
+//! Geodesy on a spherical earth: distances, bearings and projections onto paths.
+//!
+//! Coordinates are in degrees and distances in kilometres, in ```f64``` throughout as ```f32```
+//! coordinates are only accurate to about a metre. Treating the earth as a sphere of the mean
+//! radius gives distances within 0.5% of the ellipsoid, which is ample for matching vehicles
+//! and devices.
+
+use std::f64::consts::PI;
+
+/// Mean radius of the earth, in kilometres.
+pub const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Half the circumference of the earth, the furthest apart two points can be.
+pub const MAX_DISTANCE_KM: f64 = PI * EARTH_RADIUS_KM;
+
+/// A position on the earth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Point {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Point {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Point from ```f32``` coordinates, as used by GTFS feeds.
+    pub fn from_f32(latitude: f32, longitude: f32) -> Self {
+        Point::new(f64::from(latitude), f64::from(longitude))
+    }
+}
+
+/// Great circle distance between ```from``` and ```to```, by the haversine formula.
+pub fn distance_km(from: Point, to: Point) -> f64 {
+    angular_distance(from, to) * EARTH_RADIUS_KM
+}
+
+/// Distance between ```from``` and ```to``` in radians of arc.
+fn angular_distance(from: Point, to: Point) -> f64 {
+    let (lat_1, lat_2) = (from.latitude.to_radians(), to.latitude.to_radians());
+    let d_lat = lat_2 - lat_1;
+    let d_lon = (to.longitude - from.longitude).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + (d_lon / 2.0).sin().powi(2) * lat_1.cos() * lat_2.cos();
+    // rounding can take a just past 1 for antipodal points
+    let a = a.min(1.0);
+    2.0 * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+/// Initial bearing of the great circle path from ```from``` to ```to```, in degrees clockwise
+/// from north (0 to 360).
+pub fn initial_bearing(from: Point, to: Point) -> f64 {
+    let (lat_1, lat_2) = (from.latitude.to_radians(), to.latitude.to_radians());
+    let d_lon = (to.longitude - from.longitude).to_radians();
+    let y = d_lon.sin() * lat_2.cos();
+    let x = lat_1.cos() * lat_2.sin() - lat_1.sin() * lat_2.cos() * d_lon.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+/// Point reached travelling ```distance_km``` from ```start``` along the great circle with
+/// initial bearing ```bearing``` (degrees clockwise from north).
+pub fn destination(start: Point, bearing: f64, distance_km: f64) -> Point {
+    let lat_1 = start.latitude.to_radians();
+    let bearing = bearing.to_radians();
+    let angle = distance_km / EARTH_RADIUS_KM;
+
+    let lat_2 = (lat_1.sin() * angle.cos() + lat_1.cos() * angle.sin() * bearing.cos()).asin();
+    let d_lon =
+        (bearing.sin() * angle.sin() * lat_1.cos()).atan2(angle.cos() - lat_1.sin() * lat_2.sin());
+    Point::new(
+        lat_2.to_degrees(),
+        normalise_longitude(start.longitude + d_lon.to_degrees()),
+    )
+}
+
+/// Longitude in the range -180 to 180.
+fn normalise_longitude(longitude: f64) -> f64 {
+    (longitude + 540.0).rem_euclid(360.0) - 180.0
+}
+
+/// Distance of ```point``` from the great circle through ```start``` and ```end```, positive if
+/// to the right of the path from ```start``` to ```end``` and negative if to the left.
+pub fn cross_track_km(point: Point, start: Point, end: Point) -> f64 {
+    let (distance, angle) = relative_to_path(point, start, end);
+    (distance.sin() * angle.sin()).asin() * EARTH_RADIUS_KM
+}
+
+/// Distance from ```start``` towards ```end``` of the nearest point to ```point``` on the great
+/// circle through them, negative if behind ```start```.
+pub fn along_track_km(point: Point, start: Point, end: Point) -> f64 {
+    let (distance, angle) = relative_to_path(point, start, end);
+    // tan(along) = tan(distance) cos(angle), for the right angled triangle with the path
+    (distance.sin() * angle.cos()).atan2(distance.cos()) * EARTH_RADIUS_KM
+}
+
+/// Angular distance of ```point``` from ```start```, and the angle between the paths from
+/// ```start``` to ```end``` and to ```point```, both in radians.
+fn relative_to_path(point: Point, start: Point, end: Point) -> (f64, f64) {
+    let angle = initial_bearing(start, point) - initial_bearing(start, end);
+    (angular_distance(start, point), angle.to_radians())
+}
+
+/// Nearest point of a segment to a position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentProjection {
+    /// Distance of the nearest point from the start of the segment, in kilometres.
+    pub along_km: f64,
+    /// Distance of the position from the segment, in kilometres.
+    pub distance_km: f64,
+}
+
+/// Nearest point to ```point``` of the segment of a polyline from ```start``` to ```end```, which
+/// is one of its ends if ```point``` is beyond them.
+pub fn project_onto_segment(point: Point, start: Point, end: Point) -> SegmentProjection {
+    let length = distance_km(start, end);
+    let along = if length > 0.0 {
+        along_track_km(point, start, end)
+    } else {
+        0.0
+    };
+    if along <= 0.0 {
+        SegmentProjection {
+            along_km: 0.0,
+            distance_km: distance_km(point, start),
+        }
+    } else if along >= length {
+        SegmentProjection {
+            along_km: length,
+            distance_km: distance_km(point, end),
+        }
+    } else {
+        SegmentProjection {
+            along_km: along,
+            distance_km: cross_track_km(point, start, end).abs(),
+        }
+    }
+}
+
+/// Latitude and longitude bounds, for quickly ruling out points before measuring distances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_latitude: f64,
+    pub max_latitude: f64,
+    pub min_longitude: f64,
+    pub max_longitude: f64,
+}
+
+impl BoundingBox {
+    /// Smallest box containing every point within ```radius_km``` of ```center```. Near the poles
+    /// or the antimeridian the box spans every longitude.
+    pub fn around(center: Point, radius_km: f64) -> Self {
+        let angle = (radius_km / EARTH_RADIUS_KM).to_degrees();
+        let min_latitude = center.latitude - angle;
+        let max_latitude = center.latitude + angle;
+        if min_latitude <= -90.0 || max_latitude >= 90.0 {
+            return BoundingBox {
+                min_latitude: min_latitude.max(-90.0),
+                max_latitude: max_latitude.min(90.0),
+                min_longitude: -180.0,
+                max_longitude: 180.0,
+            };
+        }
+
+        // the widest longitude reached, where the circle touches a meridian
+        let d_lon = ((radius_km / EARTH_RADIUS_KM).sin() / center.latitude.to_radians().cos())
+            .min(1.0)
+            .asin()
+            .to_degrees();
+        let (min_longitude, max_longitude) =
+            if center.longitude - d_lon < -180.0 || center.longitude + d_lon > 180.0 {
+                (-180.0, 180.0)
+            } else {
+                (center.longitude - d_lon, center.longitude + d_lon)
+            };
+        BoundingBox {
+            min_latitude,
+            max_latitude,
+            min_longitude,
+            max_longitude,
+        }
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        (self.min_latitude..=self.max_latitude).contains(&point.latitude)
+            && (self.min_longitude..=self.max_longitude).contains(&point.longitude)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of a degree of a great circle.
+    const DEGREE_KM: f64 = MAX_DISTANCE_KM / 180.0;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{:} is not within {:} of {:}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    /// Deterministic pseudo-random points (xorshift), so failures are reproducible.
+    struct Points(u64);
+
+    impl Points {
+        fn uniform(&mut self, min: f64, max: f64) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            min + (max - min) * (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        /// Point away from the poles, where bearings are ill-defined.
+        fn next(&mut self) -> Point {
+            Point::new(self.uniform(-80.0, 80.0), self.uniform(-180.0, 180.0))
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        let origin = Point::new(0.0, 0.0);
+        let north_pole = Point::new(90.0, 0.0);
+        assert_close(distance_km(origin, Point::new(0.0, 1.0)), DEGREE_KM, 1e-9);
+        assert_close(distance_km(origin, north_pole), MAX_DISTANCE_KM / 2.0, 1e-9);
+        assert_close(
+            distance_km(origin, Point::new(0.0, 180.0)),
+            MAX_DISTANCE_KM,
+            1e-9,
+        );
+        // Nashville to Los Angeles airports, the usual test of the haversine formula
+        let nashville = Point::new(36.12, -86.67);
+        let los_angeles = Point::new(33.94, -118.40);
+        assert_close(distance_km(nashville, los_angeles), 2886.444, 1e-3);
+
+        assert_close(initial_bearing(origin, Point::new(0.0, 1.0)), 90.0, 1e-9);
+        assert_close(initial_bearing(origin, Point::new(-1.0, 0.0)), 180.0, 1e-9);
+        assert_close(initial_bearing(origin, Point::new(0.0, -1.0)), 270.0, 1e-9);
+
+        let east = destination(origin, 90.0, MAX_DISTANCE_KM / 2.0);
+        assert_close(east.latitude, 0.0, 1e-9);
+        assert_close(east.longitude, 90.0, 1e-9);
+        assert_close(destination(origin, 0.0, DEGREE_KM).latitude, 1.0, 1e-9);
+        // across the antimeridian
+        let west = destination(Point::new(0.0, 179.5), 90.0, DEGREE_KM);
+        assert_close(west.longitude, -179.5, 1e-9);
+
+        // a degree north of the equator, a degree east of the start of an eastward path
+        let (start, end) = (Point::new(0.0, -1.0), Point::new(0.0, 1.0));
+        let point = Point::new(1.0, 0.0);
+        assert_close(cross_track_km(point, start, end), -DEGREE_KM, 1e-9);
+        assert_close(cross_track_km(point, end, start), DEGREE_KM, 1e-9);
+        assert_close(along_track_km(point, start, end), DEGREE_KM, 1e-9);
+        assert_close(
+            along_track_km(Point::new(0.0, -2.0), start, end),
+            -DEGREE_KM,
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn distance_properties() {
+        let mut points = Points(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..1000 {
+            let (a, b, c) = (points.next(), points.next(), points.next());
+            let (ab, bc, ac) = (distance_km(a, b), distance_km(b, c), distance_km(a, c));
+            assert_close(ab, distance_km(b, a), 1e-9);
+            assert!((0.0..=MAX_DISTANCE_KM).contains(&ab));
+            assert!(ac <= ab + bc + 1e-9);
+            assert_eq!(distance_km(a, a), 0.0);
+        }
+    }
+
+    #[test]
+    fn destination_inverts_bearing_and_distance() {
+        let mut points = Points(0x2545_f491_4f6c_dd1d);
+        for _ in 0..1000 {
+            let (start, end) = (points.next(), points.next());
+            let distance = distance_km(start, end);
+            // the bearing to nearly antipodal points is ill-conditioned
+            if distance > MAX_DISTANCE_KM * 0.99 {
+                continue;
+            }
+
+            let reached = destination(start, initial_bearing(start, end), distance);
+            assert_close(distance_km(reached, end), 0.0, 1e-6);
+        }
+    }
+
+    #[test]
+    fn projections_onto_segments() {
+        let mut points = Points(0xdead_beef_cafe_f00d);
+        for _ in 0..1000 {
+            let start = points.next();
+            let bearing = points.uniform(0.0, 360.0);
+            let length = points.uniform(0.01, 50.0);
+            let end = destination(start, bearing, length);
+
+            // a point beside the segment, offset to the right of a point along it
+            let along = points.uniform(0.0, length);
+            let offset = points.uniform(0.001, 1.0);
+            let beside = destination(start, bearing, along);
+            let heading = if length - along > along {
+                initial_bearing(beside, end)
+            } else {
+                initial_bearing(beside, start) + 180.0
+            };
+            let point = destination(beside, heading + 90.0, offset);
+            assert_close(cross_track_km(point, start, end), offset, 1e-6);
+            assert_close(along_track_km(point, start, end), along, 1e-6);
+            let projection = project_onto_segment(point, start, end);
+            assert_close(projection.along_km, along, 1e-6);
+            assert_close(projection.distance_km, offset, 1e-6);
+
+            // before the start the segment is nearest at its start
+            let behind = destination(start, bearing + 180.0, offset);
+            let projection = project_onto_segment(behind, start, end);
+            assert_eq!(projection.along_km, 0.0);
+            assert_close(projection.distance_km, offset, 1e-6);
+            assert!(cross_track_km(behind, start, end).abs() <= distance_km(behind, start));
+        }
+    }
+
+    #[test]
+    fn bounding_boxes_contain_circles() {
+        let mut points = Points(0x0123_4567_89ab_cdef);
+        for _ in 0..1000 {
+            let center = points.next();
+            let radius = points.uniform(0.0, 500.0);
+            let bounds = BoundingBox::around(center, radius);
+            for _ in 0..10 {
+                let inside = destination(center, points.uniform(0.0, 360.0), radius * 0.999);
+                assert!(bounds.contains(inside), "{:?} outside {:?}", inside, bounds);
+            }
+        }
+
+        let brisbane = BoundingBox::around(Point::new(-27.47, 153.02), 10.0);
+        assert!(!brisbane.contains(Point::new(-27.47, 153.2)));
+        assert!(!brisbane.contains(Point::new(-27.6, 153.02)));
+        let pole = BoundingBox::around(Point::new(89.95, 0.0), 10.0);
+        assert!(pole.contains(Point::new(89.99, 120.0)));
+    }
+}
@@ -0,0 +1,223 @@
+//! Recording of fetched GTFS-RT feeds to disk, and replaying them as if connected live.
+//!
+//! An archive is a directory with a subdirectory for each feed type (```trip_updates```,
+//! ```vehicle_positions``` and ```alerts```), holding every recorded feed as a protocol buffer
+//! file named after the time it was fetched, in milliseconds since the Unix epoch (e.g.
+//! ```trip_updates/1628553600000.pb```).
+
+use crate::gtfs::gtfs_real_time::{FeedMessage, FeedType, GtfsRtError};
+use prost::Message;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+const FEED_TYPES: [FeedType; 3] = [
+    FeedType::TripUpdate,
+    FeedType::VehiclePosition,
+    FeedType::Alert,
+];
+
+/// Subdirectory of an archive holding the ```feed_type``` feeds.
+fn directory(feed_type: FeedType) -> &'static str {
+    match feed_type {
+        FeedType::TripUpdate => "trip_updates",
+        FeedType::VehiclePosition => "vehicle_positions",
+        FeedType::Alert => "alerts",
+    }
+}
+
+/// Writes feeds to an archive as they are fetched.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Record to the archive at ```path```, creating it if needed.
+    pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        for &feed_type in FEED_TYPES.iter() {
+            std::fs::create_dir_all(path.join(directory(feed_type)))?;
+        }
+        Ok(Recorder { path })
+    }
+
+    /// Write ```feed``` fetched at ```fetched```, returning the path of its file.
+    pub async fn record(
+        &self,
+        feed_type: FeedType,
+        feed: &FeedMessage,
+        fetched: SystemTime,
+    ) -> std::io::Result<PathBuf> {
+        let millis = fetched
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let path = self
+            .path
+            .join(directory(feed_type))
+            .join(format!("{:}.pb", millis));
+        tokio::fs::write(&path, feed.encode_to_vec()).await?;
+        Ok(path)
+    }
+}
+
+/// Replays an archive, returning the feed that was current at the same point of the recording.
+#[derive(Debug)]
+pub struct Replay {
+    // recorded feeds of each type, as (fetch time in milliseconds, path) in order of fetching
+    feeds: HashMap<FeedType, Vec<(u64, PathBuf)>>,
+    recording_start: u64,
+    replay_start: Instant,
+    speed: f64,
+}
+
+impl Replay {
+    /// Replay the archive at ```path``` from its first recorded feed, ```speed``` times faster
+    /// than it was recorded (```1.0``` for real speed).
+    pub fn new<P: AsRef<Path>>(path: P, speed: f64) -> std::io::Result<Self> {
+        let mut feeds = HashMap::new();
+        for &feed_type in FEED_TYPES.iter() {
+            let mut recorded = Vec::new();
+            let feed_directory = path.as_ref().join(directory(feed_type));
+            if feed_directory.is_dir() {
+                for entry in std::fs::read_dir(feed_directory)? {
+                    let path = entry?.path();
+                    let fetched = path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .and_then(|stem| stem.parse::<u64>().ok());
+                    if let Some(fetched) = fetched {
+                        recorded.push((fetched, path));
+                    }
+                }
+            }
+            recorded.sort();
+            feeds.insert(feed_type, recorded);
+        }
+
+        let recording_start = feeds
+            .values()
+            .filter_map(|recorded| recorded.first())
+            .map(|(fetched, _)| *fetched)
+            .min()
+            .unwrap_or(0);
+        Ok(Replay {
+            feeds,
+            recording_start,
+            replay_start: Instant::now(),
+            speed,
+        })
+    }
+
+    /// Time of the recording currently being replayed, in milliseconds since the Unix epoch.
+    pub fn recording_time(&self) -> u64 {
+        let elapsed = self.replay_start.elapsed().as_secs_f64() * self.speed;
+        self.recording_start + (elapsed * 1000.0) as u64
+    }
+
+    /// Latest ```feed_type``` feed recorded by the current recording time, or the first if none
+    /// had been recorded yet.
+    pub async fn feed(&self, feed_type: FeedType) -> Result<FeedMessage, GtfsRtError> {
+        self.feed_at(feed_type, self.recording_time()).await
+    }
+
+    /// Latest ```feed_type``` feed recorded by ```time```, in milliseconds since the Unix epoch, or
+    /// the first if none had been recorded yet.
+    pub async fn feed_at(
+        &self,
+        feed_type: FeedType,
+        time: u64,
+    ) -> Result<FeedMessage, GtfsRtError> {
+        let recorded = match self.feeds.get(&feed_type) {
+            Some(recorded) if !recorded.is_empty() => recorded,
+            _ => return Err(GtfsRtError::MissingFeed(feed_type)),
+        };
+        let current = recorded
+            .iter()
+            .rev()
+            .find(|(fetched, _)| *fetched <= time)
+            .unwrap_or(&recorded[0]);
+
+        let bytes = tokio::fs::read(&current.1).await?;
+        Ok(FeedMessage::decode(&bytes[..])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::FeedHeader;
+    use std::time::Duration;
+
+    fn feed(timestamp: u64) -> FeedMessage {
+        FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: String::from("2.0"),
+                timestamp: Some(timestamp),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let path = std::env::temp_dir().join(format!("gtfs-rt-archive-{:}", std::process::id()));
+        let recorder = Recorder::new(&path).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_628_553_600);
+        for (offset, timestamp) in [(0, 100), (30, 130), (60, 160)].iter() {
+            let fetched = start + Duration::from_secs(*offset);
+            recorder
+                .record(FeedType::TripUpdate, &feed(*timestamp), fetched)
+                .await
+                .unwrap();
+        }
+        recorder
+            .record(FeedType::Alert, &feed(90), start + Duration::from_secs(20))
+            .await
+            .unwrap();
+
+        let replay = Replay::new(&path, 30.0).unwrap();
+        let millis = |offset: u64| (1_628_553_600 + offset) * 1000;
+        assert_eq!(
+            replay
+                .feed_at(FeedType::TripUpdate, millis(0))
+                .await
+                .unwrap(),
+            feed(100)
+        );
+        assert_eq!(
+            replay
+                .feed_at(FeedType::TripUpdate, millis(29))
+                .await
+                .unwrap(),
+            feed(100)
+        );
+        assert_eq!(
+            replay
+                .feed_at(FeedType::TripUpdate, millis(45))
+                .await
+                .unwrap(),
+            feed(130)
+        );
+        assert_eq!(
+            replay
+                .feed_at(FeedType::TripUpdate, millis(90))
+                .await
+                .unwrap(),
+            feed(160)
+        );
+        // before the first alert was recorded
+        assert_eq!(
+            replay.feed_at(FeedType::Alert, millis(0)).await.unwrap(),
+            feed(90)
+        );
+        assert!(matches!(
+            replay.feed(FeedType::VehiclePosition).await,
+            Err(GtfsRtError::MissingFeed(FeedType::VehiclePosition))
+        ));
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
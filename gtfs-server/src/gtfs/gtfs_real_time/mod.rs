@@ -2,15 +2,26 @@
 //!
 //! Requires GTFS-RT feed to supply timestamp, otherwise need to manually wait for new feeds or block normally todo! -> call update instead?
 
+pub mod archive;
+pub mod poller;
+pub mod source;
+
+use crate::gtfs::gtfs_real_time::archive::{Recorder, Replay};
+use crate::gtfs::gtfs_real_time::source::{FeedSource, ReplaySource};
 use prost::{DecodeError, Message};
 use reqwest;
-use reqwest::Error;
-use tokio::time::{sleep, Duration};
+use reqwest::{Error, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::time::{sleep, timeout, Duration};
 
 // GTFS-RT definitions.
 include!(concat!(env!("OUT_DIR"), "/gtf_sv2.realtime.rs"));
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FeedType {
     TripUpdate,
     VehiclePosition,
@@ -32,7 +43,29 @@ impl std::fmt::Display for FeedType {
 pub enum GtfsRtError {
     ProtobufDecodeError(prost::DecodeError),
     DownloadError(reqwest::Error),
+    HttpStatusError(StatusCode),
     MissingFeed(FeedType),
+    FileError(std::io::Error),
+    TimeoutError(String),
+    EmptySource(String),
+}
+
+impl GtfsRtError {
+    /// Whether the request may succeed if retried later, such as after a timeout or while the
+    /// feed server is overloaded.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            GtfsRtError::DownloadError(_)
+            | GtfsRtError::TimeoutError(_)
+            | GtfsRtError::EmptySource(_) => true,
+            GtfsRtError::HttpStatusError(status) => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            GtfsRtError::ProtobufDecodeError(_)
+            | GtfsRtError::MissingFeed(_)
+            | GtfsRtError::FileError(_) => false,
+        }
+    }
 }
 
 impl std::error::Error for GtfsRtError {}
@@ -43,7 +76,11 @@ impl std::fmt::Display for GtfsRtError {
         match self {
             ProtobufDecodeError(e) => write!(f, "Error decoding protocol-buffer\n{:}", e),
             DownloadError(e) => write!(f, "Error obtaining real-time feed from url\n{:}", e),
+            HttpStatusError(status) => write!(f, "Real-time feed server responded {:}", status),
             MissingFeed(feed) => write!(f, "Cannot query {:} feed, missing feed url", feed),
+            FileError(e) => write!(f, "Error accessing real-time feed file\n{:}", e),
+            TimeoutError(source) => write!(f, "Timed out retrieving feed from {:}", source),
+            EmptySource(source) => write!(f, "No feed has been supplied by {:}", source),
         }
     }
 }
@@ -54,27 +91,93 @@ impl From<reqwest::Error> for GtfsRtError {
     }
 }
 
+impl From<std::io::Error> for GtfsRtError {
+    fn from(e: std::io::Error) -> Self {
+        GtfsRtError::FileError(e)
+    }
+}
+
 impl From<prost::DecodeError> for GtfsRtError {
     fn from(e: DecodeError) -> Self {
         GtfsRtError::ProtobufDecodeError(e)
     }
 }
 
+/// How feeds are requested, and retried after failing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchPolicy {
+    /// Time allowed for retrieving a feed, including downloading it.
+    pub timeout: Duration,
+    /// Delay before the first retry of a failed request, doubled for each following failure.
+    pub initial_backoff: Duration,
+    /// Longest delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        FetchPolicy {
+            timeout: Duration::from_secs(10),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Exponentially increasing delays between retries, with jitter so that clients which failed
+/// together do not retry together.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            failures: 0,
+        }
+    }
+
+    /// Delay before retrying after another failure: between 1 and 1.5 times ```initial``` doubled
+    /// for each previous failure, at most ```max```.
+    pub fn failure(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .checked_mul(1 << self.failures.min(20))
+            .map_or(self.max, |delay| delay.min(self.max));
+        self.failures = self.failures.saturating_add(1);
+
+        let random = RandomState::new().build_hasher().finish();
+        let jitter = (delay / 2).mul_f64(random as f64 / u64::MAX as f64);
+        (delay + jitter).min(self.max)
+    }
+
+    /// Restart from the initial delay after a success.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
 /// Connection to a GTFS-RT feed.
 pub struct GtfsRt {
-    // rt_url: String, // todo: it is possible this link has only the vehicle positions
-    connection: reqwest::Client,
-    trip_updates_url: Option<String>,
-    vehicle_positions_url: Option<String>,
-    alerts_url: Option<String>,
-    latest_timestamp: u64,
-    // how long to wait for between querying feed for new data (optional)
+    trip_updates: Option<Box<dyn FeedSource>>,
+    vehicle_positions: Option<Box<dyn FeedSource>>,
+    alerts: Option<Box<dyn FeedSource>>,
+    // header timestamp of the latest feed of each type from each source
+    latest_timestamps: Mutex<HashMap<(FeedType, String), u64>>,
+    policy: FetchPolicy,
+    recorder: Option<Recorder>,
 }
 
 impl GtfsRt {
     /// Create a new GTFS-RT instance for a single-url RT feed.
     /// Assumes the single-url RT feed contains vehicle positions. If a different feed is supplied,
     /// create a new instance from multiple urls and set the unused feeds to ```None```.
+    /// Urls may also be ```file://``` paths of protocol buffer files.
     ///
     /// # Example
     ///
@@ -83,13 +186,8 @@ impl GtfsRt {
     /// let gtfs_rt = rt::GtfsRt::new_single_url(URL);
     /// ```
     pub fn new_single_url(rt_url: &str) -> Self {
-        GtfsRt {
-            connection: reqwest::Client::new(),
-            trip_updates_url: None,
-            vehicle_positions_url: Some(String::from(rt_url)),
-            alerts_url: None,
-            latest_timestamp: 0,
-        }
+        let client = reqwest::Client::new();
+        GtfsRt::new_optional(None, Some(source::from_url(rt_url, &client)), None)
     }
 
     /// Create a new GTFS-RT instance with all feed types (trip updates, vehicle positions, alerts)
@@ -102,42 +200,95 @@ impl GtfsRt {
     /// let gtfs_rt = rt::GtfsRt::new(TRIP_UPDATES_URL, VEHICLE_POSITIONS_URL, ALERTS_URL);
     /// ```
     pub fn new(trip_updates_url: &str, vehicle_positions_url: &str, alerts_url: &str) -> Self {
-        GtfsRt {
-            connection: reqwest::Client::new(),
-            trip_updates_url: Some(String::from(trip_updates_url)),
-            vehicle_positions_url: Some(String::from(vehicle_positions_url)),
-            alerts_url: Some(String::from(alerts_url)),
-            latest_timestamp: 0,
-        }
+        let client = reqwest::Client::new();
+        GtfsRt::new_optional(
+            Some(source::from_url(trip_updates_url, &client)),
+            Some(source::from_url(vehicle_positions_url, &client)),
+            Some(source::from_url(alerts_url, &client)),
+        )
     }
 
     /// Create a new GTFS-RT instance with any combination of feed types (trip updates, vehicle
-    /// positions, alerts) supplied.
+    /// positions, alerts) supplied, from any source.
     ///
     /// # Example
     ///
-    /// ```
+    /// ```no_run
     /// use gtfs_server::gtfs::gtfs_real_time as rt;
-    /// let gtfs_rt = rt::GtfsRt::new(TRIP_UPDATES_URL, VEHICLE_POSITIONS_URL, ALERTS_URL);
+    /// use gtfs_server::gtfs::gtfs_real_time::source::{self, FileSource, HttpSource};
+    /// # const TRIP_UPDATES_URL: &str = "https://example.com/TripUpdates";
+    /// let (sender, alerts) = source::channel();
+    /// let gtfs_rt = rt::GtfsRt::new_optional(
+    ///     Some(Box::new(HttpSource::new(TRIP_UPDATES_URL))),
+    ///     Some(Box::new(FileSource::new("vehicle_positions.pb"))),
+    ///     Some(Box::new(alerts)),
+    /// );
     /// ```
     pub fn new_optional(
-        trip_updates_url: Option<&str>,
-        vehicle_positions_url: Option<&str>,
-        alerts_url: Option<&str>,
+        trip_updates: Option<Box<dyn FeedSource>>,
+        vehicle_positions: Option<Box<dyn FeedSource>>,
+        alerts: Option<Box<dyn FeedSource>>,
     ) -> Self {
         GtfsRt {
-            connection: reqwest::Client::new(),
-            trip_updates_url: trip_updates_url.map(|s| String::from(s)),
-            vehicle_positions_url: vehicle_positions_url.map(|s| String::from(s)),
-            alerts_url: alerts_url.map(|s| String::from(s)),
-            latest_timestamp: 0,
+            trip_updates,
+            vehicle_positions,
+            alerts,
+            latest_timestamps: Mutex::new(HashMap::new()),
+            policy: FetchPolicy::default(),
+            recorder: None,
         }
     }
 
-    async fn update(&self, url: &str) -> Result<FeedMessage, GtfsRtError> {
-        Ok(FeedMessage::decode(
-            self.connection.get(url).send().await?.bytes().await?,
-        )?)
+    /// Create a GTFS-RT instance replaying the feeds of an archive (see ```archive```) instead of
+    /// connecting to a feed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use gtfs_server::gtfs::gtfs_real_time as rt;
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let replay = rt::archive::Replay::new("recordings/2021-08-10", 10.0)?;
+    /// let gtfs_rt = rt::GtfsRt::from_replay(replay);
+    /// let trip_update_feed = gtfs_rt.latest(rt::FeedType::TripUpdate).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_replay(replay: Replay) -> Self {
+        let replay = Arc::new(replay);
+        let source = |feed_type| -> Option<Box<dyn FeedSource>> {
+            Some(Box::new(ReplaySource::new(replay.clone(), feed_type)))
+        };
+        GtfsRt::new_optional(
+            source(FeedType::TripUpdate),
+            source(FeedType::VehiclePosition),
+            source(FeedType::Alert),
+        )
+    }
+
+    /// Record every new feed retrieved to an archive, such as to replay it later.
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Source of the ```feed_type``` feed.
+    fn source(&self, feed_type: FeedType) -> Result<&dyn FeedSource, GtfsRtError> {
+        let source = match feed_type {
+            FeedType::TripUpdate => &self.trip_updates,
+            FeedType::VehiclePosition => &self.vehicle_positions,
+            FeedType::Alert => &self.alerts,
+        };
+        source.as_deref().ok_or(GtfsRtError::MissingFeed(feed_type))
+    }
+
+    /// Policy used to request feeds.
+    pub fn fetch_policy(&self) -> &FetchPolicy {
+        &self.policy
+    }
+
+    /// Replace the policy used to request feeds, which is ```FetchPolicy::default()``` unless
+    /// set.
+    pub fn set_fetch_policy(&mut self, policy: FetchPolicy) {
+        self.policy = policy;
     }
 
     /// Retrieve the latest trip update dataset.
@@ -154,10 +305,7 @@ impl GtfsRt {
     /// }
     /// ```
     pub async fn update_trip_updates(&self) -> Result<FeedMessage, GtfsRtError> {
-        match &self.trip_updates_url {
-            None => Err(GtfsRtError::MissingFeed(FeedType::TripUpdate)),
-            Some(url) => self.update(url).await,
-        }
+        self.update_feed(FeedType::TripUpdate).await
     }
 
     /// Retrieve the latest vehicle_positions dataset.
@@ -174,10 +322,7 @@ impl GtfsRt {
     /// }
     /// ```
     pub async fn update_vehicle_positions(&self) -> Result<FeedMessage, GtfsRtError> {
-        match &self.vehicle_positions_url {
-            None => Err(GtfsRtError::MissingFeed(FeedType::VehiclePosition)),
-            Some(url) => self.update(url).await,
-        }
+        self.update_feed(FeedType::VehiclePosition).await
     }
 
     /// Retrieve the latest alerts dataset.
@@ -194,37 +339,83 @@ impl GtfsRt {
     /// }
     /// ```
     pub async fn update_alerts(&self) -> Result<FeedMessage, GtfsRtError> {
-        match &self.alerts_url {
-            None => Err(GtfsRtError::MissingFeed(FeedType::Alert)),
-            Some(url) => self.update(url).await,
+        self.update_feed(FeedType::Alert).await
+    }
+
+    /// Retrieve the latest dataset of the ```feed_type``` feed.
+    pub async fn update_feed(&self, feed_type: FeedType) -> Result<FeedMessage, GtfsRtError> {
+        self.fetch(feed_type).await.map(|(feed, _)| feed)
+    }
+
+    /// Retrieve the latest dataset of the ```feed_type``` feed, and whether it is newer than every
+    /// feed previously retrieved from its source. Feeds without a timestamp are always new.
+    async fn fetch(&self, feed_type: FeedType) -> Result<(FeedMessage, bool), GtfsRtError> {
+        let source = self.source(feed_type)?;
+        let feed = match timeout(self.policy.timeout, source.fetch()).await {
+            Ok(feed) => feed?,
+            Err(_) => return Err(GtfsRtError::TimeoutError(source.name())),
+        };
+        let newer = match feed.header.timestamp {
+            None => true,
+            Some(time) => self.record_timestamp(feed_type, &source.name(), time),
+        };
+        // servers answering 304 Not Modified resupply the previous feed, which is already recorded
+        if let (true, Some(recorder)) = (newer, &self.recorder) {
+            // a full disk shouldn't interrupt the live feed
+            if let Err(e) = recorder.record(feed_type, &feed, SystemTime::now()).await {
+                eprintln!("Unable to record {:} feed\n{:}", feed_type, e);
+            }
         }
+        Ok((feed, newer))
     }
 
     /// Wait for a new set of data to arrive.
     /// Block on this function to only retrieve more data when new data is available.
     /// If GTFS-RT feed does not supply timestamp then obtains and returns most recent data.
-    pub async fn latest(&mut self, feed_type: FeedType) -> Result<FeedMessage, GtfsRtError> {
+    /// Freshness is tracked separately for each feed type and source, so alternating between feeds
+    /// returns new data of each. Transient errors are retried, backing off as set by the fetch
+    /// policy.
+    pub async fn latest(&self, feed_type: FeedType) -> Result<FeedMessage, GtfsRtError> {
+        let mut backoff = Backoff::new(self.policy.initial_backoff, self.policy.max_backoff);
         loop {
-            let fm = match feed_type {
-                FeedType::TripUpdate => self.update_trip_updates().await?,
-                FeedType::VehiclePosition => self.update_vehicle_positions().await?,
-                FeedType::Alert => self.update_alerts().await?,
-            };
-
-            match fm.header.timestamp {
-                None => return Ok(fm),
-                Some(time) => {
-                    let prev_time = self.latest_timestamp;
-                    self.latest_timestamp = time;
-                    if time > prev_time {
-                        return Ok(fm);
-                    }
+            let (fm, newer) = match self.fetch(feed_type).await {
+                Ok(fetched) => fetched,
+                Err(e) if e.is_transient() => {
+                    sleep(backoff.failure()).await;
+                    continue;
                 }
+                Err(e) => return Err(e),
             };
+            backoff.reset();
+
+            if newer {
+                return Ok(fm);
+            }
 
             sleep(Duration::from_secs(5)).await;
         }
     }
+
+    /// Record the header ```timestamp``` of a ```feed_type``` feed from the source ```name```,
+    /// returning whether it is newer than every previous feed.
+    fn record_timestamp(&self, feed_type: FeedType, name: &str, timestamp: u64) -> bool {
+        let mut latest_timestamps = self
+            .latest_timestamps
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        match latest_timestamps.get_mut(&(feed_type, String::from(name))) {
+            Some(latest) => {
+                let newer = timestamp > *latest;
+                *latest = (*latest).max(timestamp);
+                newer
+            }
+            None => {
+                let key = (feed_type, String::from(name));
+                latest_timestamps.insert(key, timestamp);
+                true
+            }
+        }
+    }
 }
 
 /// Retrieve a FeedMessage item from a real time feed url without setting up a GtfsRt instance.
@@ -243,3 +434,40 @@ pub async fn feed_message_from_url(url: &str) -> Result<FeedMessage, GtfsRtError
     let mut buf: &[u8] = &bytes[..];
     Ok(FeedMessage::decode(&mut buf)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_delays() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(20));
+        let delays: Vec<Duration> = (0..6).map(|_| backoff.failure()).collect();
+        for (failures, delay) in delays.iter().enumerate().take(3) {
+            let base = Duration::from_secs(2 << failures);
+            assert!(*delay >= base && *delay <= base * 3 / 2);
+        }
+        assert_eq!(delays[5], Duration::from_secs(20));
+
+        backoff.reset();
+        assert!(backoff.failure() <= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn timestamps_per_feed() {
+        let gtfs_rt = GtfsRt::new("http://rt/trips", "http://rt/vehicles", "http://rt/alerts");
+        assert!(gtfs_rt.record_timestamp(FeedType::VehiclePosition, "http://rt/vehicles", 200));
+        // an older trip update feed is still new data
+        assert!(gtfs_rt.record_timestamp(FeedType::TripUpdate, "http://rt/trips", 100));
+        assert!(!gtfs_rt.record_timestamp(FeedType::TripUpdate, "http://rt/trips", 100));
+        assert!(gtfs_rt.record_timestamp(FeedType::TripUpdate, "http://rt/trips", 150));
+        assert!(!gtfs_rt.record_timestamp(FeedType::VehiclePosition, "http://rt/vehicles", 200));
+        // a feed going back in time isn't new, nor is one between it and the latest
+        assert!(!gtfs_rt.record_timestamp(FeedType::TripUpdate, "http://rt/trips", 50));
+        assert!(!gtfs_rt.record_timestamp(FeedType::TripUpdate, "http://rt/trips", 120));
+        assert!(gtfs_rt.record_timestamp(FeedType::TripUpdate, "http://rt/trips", 160));
+
+        // the same feed type from another url
+        assert!(gtfs_rt.record_timestamp(FeedType::VehiclePosition, "http://other/vehicles", 50));
+    }
+}
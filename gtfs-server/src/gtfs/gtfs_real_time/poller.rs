@@ -0,0 +1,197 @@
+//! Background polling of the GTFS-RT feeds.
+//!
+//! Each feed is downloaded by its own task on its own interval, and the decoded messages are
+//! published as a ```RealtimeSnapshot``` through a ```tokio::sync::watch``` channel. Readers
+//! always see the most recent version without doing any network I/O, and may wait for the next
+//! version with ```RealtimeReceiver::changed```.
+
+use crate::gtfs::gtfs_real_time::{Backoff, FeedMessage, FeedType, GtfsRt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// Receiver of the snapshots published by a poller.
+pub type RealtimeReceiver = watch::Receiver<Arc<RealtimeSnapshot>>;
+
+/// Latest data of each GTFS-RT feed.
+#[derive(Debug, Clone, Default)]
+pub struct RealtimeSnapshot {
+    /// Incremented with every update, so 0 until a feed has been received.
+    pub version: u64,
+    pub trip_updates: Option<Arc<FeedMessage>>,
+    pub vehicle_positions: Option<Arc<FeedMessage>>,
+    pub alerts: Option<Arc<FeedMessage>>,
+    // when each feed was last replaced
+    received: HashMap<FeedType, SystemTime>,
+}
+
+impl RealtimeSnapshot {
+    /// Data of the ```feed_type``` feed, if it has been received.
+    pub fn feed(&self, feed_type: FeedType) -> Option<&FeedMessage> {
+        match feed_type {
+            FeedType::TripUpdate => self.trip_updates.as_deref(),
+            FeedType::VehiclePosition => self.vehicle_positions.as_deref(),
+            FeedType::Alert => self.alerts.as_deref(),
+        }
+    }
+
+    /// When the data of the ```feed_type``` feed was received, if it has been.
+    pub fn received(&self, feed_type: FeedType) -> Option<SystemTime> {
+        self.received.get(&feed_type).copied()
+    }
+
+    /// Replace the data of the ```feed_type``` feed, creating a new version.
+    pub fn update(&mut self, feed_type: FeedType, feed: FeedMessage) {
+        self.update_at(feed_type, feed, SystemTime::now());
+    }
+
+    /// Replace the data of the ```feed_type``` feed with ```feed``` received at ```received```,
+    /// creating a new version.
+    pub fn update_at(&mut self, feed_type: FeedType, feed: FeedMessage, received: SystemTime) {
+        let feed = Some(Arc::new(feed));
+        match feed_type {
+            FeedType::TripUpdate => self.trip_updates = feed,
+            FeedType::VehiclePosition => self.vehicle_positions = feed,
+            FeedType::Alert => self.alerts = feed,
+        }
+        self.received.insert(feed_type, received);
+        self.version += 1;
+    }
+
+    /// Receiver that only ever sees this snapshot, such as for testing request processing.
+    pub fn into_receiver(self) -> RealtimeReceiver {
+        watch::channel(Arc::new(self)).1
+    }
+}
+
+/// Start polling each ```(feed, interval)``` of ```gtfs_rt``` in the background, returning the
+/// receiver of its snapshots. Feeds that fail to download keep their previous data, and polling
+/// stops once every receiver has been dropped.
+///
+/// Must be called from within a tokio runtime.
+///
+/// # Example
+///
+/// ```no_run
+/// use gtfs_server::gtfs::gtfs_real_time::{poller, FeedType, GtfsRt};
+/// use tokio::time::Duration;
+/// # const TRIP_UPDATES_URL: &str = "https://example.com/TripUpdates";
+/// # const VEHICLE_POSITIONS_URL: &str = "https://example.com/VehiclePositions";
+/// # const ALERTS_URL: &str = "https://example.com/Alerts";
+/// # #[tokio::main]
+/// # async fn main() {
+/// let gtfs_rt = GtfsRt::new(TRIP_UPDATES_URL, VEHICLE_POSITIONS_URL, ALERTS_URL);
+/// let realtime = poller::spawn(
+///     gtfs_rt,
+///     &[(FeedType::TripUpdate, Duration::from_secs(30))],
+/// );
+/// println!("{:?}", realtime.borrow().trip_updates);
+/// # }
+/// ```
+pub fn spawn(gtfs_rt: GtfsRt, feeds: &[(FeedType, Duration)]) -> RealtimeReceiver {
+    let gtfs_rt = Arc::new(gtfs_rt);
+    let (sender, receiver) = watch::channel(Arc::new(RealtimeSnapshot::default()));
+    let (updates, received) = mpsc::channel(feeds.len().max(1));
+
+    for &(feed_type, interval) in feeds {
+        tokio::spawn(poll(gtfs_rt.clone(), feed_type, interval, updates.clone()));
+    }
+    tokio::spawn(publish(received, sender));
+    receiver
+}
+
+/// Download the ```feed_type``` feed every ```interval```, until the publisher stops. After a
+/// failure the feed is retried with exponential backoff from ```interval```, up to the maximum
+/// backoff of the fetch policy.
+async fn poll(
+    gtfs_rt: Arc<GtfsRt>,
+    feed_type: FeedType,
+    interval: Duration,
+    updates: mpsc::Sender<(FeedType, FeedMessage)>,
+) {
+    let max_backoff = gtfs_rt.fetch_policy().max_backoff.max(interval);
+    let mut backoff = Backoff::new(interval, max_backoff);
+    loop {
+        let mut next = Instant::now() + interval;
+        match gtfs_rt.update_feed(feed_type).await {
+            Ok(feed) => {
+                backoff.reset();
+                if updates.send((feed_type, feed)).await.is_err() {
+                    return;
+                }
+            }
+            // keep serving the previous data until the feed recovers
+            Err(e) => {
+                eprintln!("Unable to update {:} feed\n{:}", feed_type, e);
+                next = Instant::now() + backoff.failure();
+            }
+        }
+        sleep_until(next).await;
+    }
+}
+
+/// Publish a new snapshot for every changed feed, until every receiver has been dropped.
+async fn publish(
+    mut updates: mpsc::Receiver<(FeedType, FeedMessage)>,
+    sender: watch::Sender<Arc<RealtimeSnapshot>>,
+) {
+    let mut snapshot = RealtimeSnapshot::default();
+    while let Some((feed_type, feed)) = updates.recv().await {
+        // feeds are commonly polled more often than they are generated
+        let previous = snapshot.feed(feed_type).map(|feed| feed.header.timestamp);
+        if feed.header.timestamp.is_some() && previous == Some(feed.header.timestamp) {
+            continue;
+        }
+
+        snapshot.update(feed_type, feed);
+        if sender.send(Arc::new(snapshot.clone())).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::FeedHeader;
+
+    fn feed(timestamp: u64) -> FeedMessage {
+        FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: String::from("2.0"),
+                timestamp: Some(timestamp),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_changed_feeds() {
+        let (sender, mut receiver) = watch::channel(Arc::new(RealtimeSnapshot::default()));
+        let (updates, received) = mpsc::channel(4);
+        let publisher = tokio::spawn(publish(received, sender));
+
+        updates.send((FeedType::Alert, feed(10))).await.unwrap();
+        receiver.changed().await.unwrap();
+        assert_eq!(receiver.borrow().version, 1);
+
+        // an unchanged feed is not published again
+        updates.send((FeedType::Alert, feed(10))).await.unwrap();
+        updates
+            .send((FeedType::TripUpdate, feed(20)))
+            .await
+            .unwrap();
+        receiver.changed().await.unwrap();
+        let snapshot = receiver.borrow().clone();
+        assert_eq!(snapshot.version, 2);
+        assert_eq!(snapshot.feed(FeedType::Alert), Some(&feed(10)));
+        assert_eq!(snapshot.feed(FeedType::TripUpdate), Some(&feed(20)));
+        assert_eq!(snapshot.feed(FeedType::VehiclePosition), None);
+
+        drop(updates);
+        publisher.await.unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+//! Sources a GTFS-RT feed can be retrieved from.
+//!
+//! ```GtfsRt``` retrieves each feed type from a ```FeedSource```: a server over HTTP
+//! (```HttpSource```), a protocol buffer file (```FileSource```), a recorded archive
+//! (```ReplaySource```) or feeds supplied by the program itself (```ChannelSource```), such as
+//! in tests.
+
+use crate::gtfs::gtfs_real_time::archive::Replay;
+use crate::gtfs::gtfs_real_time::{FeedMessage, FeedType, GtfsRtError};
+use prost::Message;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::watch;
+
+/// Feed being retrieved from a ```FeedSource```.
+pub type FeedFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<FeedMessage, GtfsRtError>> + Send + 'a>>;
+
+/// Source of a single GTFS-RT feed.
+pub trait FeedSource: Send + Sync {
+    /// Retrieve the current feed.
+    fn fetch(&self) -> FeedFuture<'_>;
+
+    /// Identifies the source, such as by its url, in messages and when tracking the freshness of
+    /// its feeds.
+    fn name(&self) -> String;
+}
+
+/// Source for a feed url, a ```FileSource``` for ```file://``` urls or an ```HttpSource```
+/// otherwise.
+pub fn from_url(url: &str, client: &reqwest::Client) -> Box<dyn FeedSource> {
+    match url.strip_prefix("file://") {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(HttpSource::with_client(url, client.clone())),
+    }
+}
+
+/// Last feed received, for conditional requests.
+struct CachedFeed {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    feed: FeedMessage,
+}
+
+/// Feed served over HTTP(S).
+///
+/// If the server supports conditional requests (ETag or Last-Modified headers) and the feed
+/// hasn't changed since it was last requested, the previous feed is returned without being
+/// downloaded again.
+pub struct HttpSource {
+    url: String,
+    client: reqwest::Client,
+    cache: Mutex<Option<CachedFeed>>,
+}
+
+impl HttpSource {
+    pub fn new(url: &str) -> Self {
+        HttpSource::with_client(url, reqwest::Client::new())
+    }
+
+    /// Source requesting ```url``` with ```client```, such as to share connections with other
+    /// sources.
+    pub fn with_client(url: &str, client: reqwest::Client) -> Self {
+        HttpSource {
+            url: String::from(url),
+            client,
+            cache: Mutex::new(None),
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Option<CachedFeed>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn request(&self) -> Result<FeedMessage, GtfsRtError> {
+        let mut request = self.client.get(&self.url);
+        if let Some(cached) = &*self.cache() {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = &*self.cache() {
+                return Ok(cached.feed.clone());
+            }
+        }
+        if !status.is_success() {
+            return Err(GtfsRtError::HttpStatusError(status));
+        }
+
+        let etag = response.headers().get(ETAG).cloned();
+        let last_modified = response.headers().get(LAST_MODIFIED).cloned();
+        let feed = FeedMessage::decode(response.bytes().await?)?;
+        if etag.is_some() || last_modified.is_some() {
+            *self.cache() = Some(CachedFeed {
+                etag,
+                last_modified,
+                feed: feed.clone(),
+            });
+        }
+        Ok(feed)
+    }
+}
+
+impl FeedSource for HttpSource {
+    fn fetch(&self) -> FeedFuture<'_> {
+        Box::pin(self.request())
+    }
+
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// Feed read from a protocol buffer file, such as one written by another program.
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileSource { path: path.into() }
+    }
+}
+
+impl FeedSource for FileSource {
+    fn fetch(&self) -> FeedFuture<'_> {
+        Box::pin(async move {
+            let bytes = tokio::fs::read(&self.path).await?;
+            Ok(FeedMessage::decode(&bytes[..])?)
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("file://{:}", self.path.display())
+    }
+}
+
+/// Feed of a type from an archive being replayed, see ```archive::Replay```.
+pub struct ReplaySource {
+    replay: Arc<Replay>,
+    feed_type: FeedType,
+}
+
+impl ReplaySource {
+    pub fn new(replay: Arc<Replay>, feed_type: FeedType) -> Self {
+        ReplaySource { replay, feed_type }
+    }
+}
+
+impl FeedSource for ReplaySource {
+    fn fetch(&self) -> FeedFuture<'_> {
+        Box::pin(self.replay.feed(self.feed_type))
+    }
+
+    fn name(&self) -> String {
+        format!("replay of {:} feeds", self.feed_type)
+    }
+}
+
+/// Feeds sent by the program through a ```FeedSender```, for instance to test processing of the
+/// real-time data without a network. The latest feed sent is returned until another is sent.
+pub struct ChannelSource {
+    receiver: watch::Receiver<Option<FeedMessage>>,
+}
+
+/// Sends feeds to a ```ChannelSource```.
+pub struct FeedSender {
+    sender: watch::Sender<Option<FeedMessage>>,
+}
+
+impl FeedSender {
+    /// Replace the feed returned by the source. Has no effect if the source was dropped.
+    pub fn send(&self, feed: FeedMessage) {
+        let _ = self.sender.send(Some(feed));
+    }
+}
+
+/// Create a ```ChannelSource``` and the sender of its feeds.
+pub fn channel() -> (FeedSender, ChannelSource) {
+    let (sender, receiver) = watch::channel(None);
+    (FeedSender { sender }, ChannelSource { receiver })
+}
+
+impl FeedSource for ChannelSource {
+    fn fetch(&self) -> FeedFuture<'_> {
+        let feed = self.receiver.borrow().clone();
+        Box::pin(async move { feed.ok_or_else(|| GtfsRtError::EmptySource(self.name())) })
+    }
+
+    fn name(&self) -> String {
+        String::from("channel")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::{FeedHeader, GtfsRt};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serve ```/feed``` with an ETag, counting its full responses, and fail every other path with
+    /// 503.
+    fn feed_server(downloads: Arc<AtomicUsize>) -> SocketAddr {
+        let feed = FeedMessage::default().encode_to_vec();
+        let make_service = make_service_fn(move |_| {
+            let (feed, downloads) = (feed.clone(), downloads.clone());
+            let service = service_fn(move |request: Request<Body>| {
+                let mut response = Response::new(Body::empty());
+                if request.uri().path() != "/feed" {
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                } else if request.headers().get(IF_NONE_MATCH)
+                    == Some(&HeaderValue::from_static("\"1\""))
+                {
+                    *response.status_mut() = StatusCode::NOT_MODIFIED;
+                } else {
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                    *response.body_mut() = Body::from(feed.clone());
+                    response
+                        .headers_mut()
+                        .insert(ETAG, HeaderValue::from_static("\"1\""));
+                }
+                async move { Ok::<_, Infallible>(response) }
+            });
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let server =
+            hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+        address
+    }
+
+    #[tokio::test]
+    async fn conditional_requests() {
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let address = feed_server(downloads.clone());
+        let gtfs_rt = GtfsRt::new(
+            &format!("http://{:}/feed", address),
+            &format!("http://{:}/unavailable", address),
+            "file:///nonexistent/alerts.pb",
+        );
+
+        // the unchanged feed is only downloaded once
+        assert_eq!(
+            gtfs_rt.update_trip_updates().await.unwrap(),
+            FeedMessage::default()
+        );
+        assert_eq!(
+            gtfs_rt.update_trip_updates().await.unwrap(),
+            FeedMessage::default()
+        );
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+        let error = gtfs_rt.update_vehicle_positions().await.unwrap_err();
+        assert!(matches!(
+            error,
+            GtfsRtError::HttpStatusError(StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        assert!(error.is_transient());
+        assert!(!gtfs_rt.update_alerts().await.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn file_and_channel_sources() {
+        let feed = FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: String::from("2.0"),
+                timestamp: Some(1_628_553_600),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("gtfs-rt-source-{:}.pb", std::process::id()));
+        std::fs::write(&path, feed.encode_to_vec()).unwrap();
+
+        let url = format!("file://{:}", path.display());
+        let file = from_url(&url, &reqwest::Client::new());
+        assert_eq!(file.name(), url);
+        assert_eq!(file.fetch().await.unwrap(), feed);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(file.fetch().await, Err(GtfsRtError::FileError(_))));
+
+        let (sender, channel) = channel();
+        assert!(matches!(
+            channel.fetch().await,
+            Err(GtfsRtError::EmptySource(_))
+        ));
+        sender.send(feed.clone());
+        assert_eq!(channel.fetch().await.unwrap(), feed);
+    }
+}
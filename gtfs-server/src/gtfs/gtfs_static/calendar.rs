@@ -0,0 +1,147 @@
+//! Service calendar resolution, determining which services run on a given date from calendar.txt
+//! and calendar_dates.txt.
+
+use crate::gtfs::gtfs_static::models::{Calendar, CalendarDate};
+use crate::gtfs::gtfs_static::types::ExceptionType;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Weekday};
+use std::collections::{HashMap, HashSet};
+
+/// Service calendar of a feed, indexed by service and date.
+///
+/// Feeds may define services with weekly patterns in calendar.txt, exceptions to those patterns
+/// in calendar_dates.txt, or every date of service in calendar_dates.txt alone.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceCalendar {
+    calendar: Vec<Calendar>,
+    exceptions: HashMap<NaiveDate, Vec<(String, ExceptionType)>>,
+}
+
+impl ServiceCalendar {
+    pub fn new(calendar: Vec<Calendar>, calendar_dates: Vec<CalendarDate>) -> Self {
+        let mut exceptions: HashMap<NaiveDate, Vec<(String, ExceptionType)>> = HashMap::new();
+        for calendar_date in calendar_dates {
+            exceptions
+                .entry(calendar_date.date)
+                .or_default()
+                .push((calendar_date.service_id, calendar_date.exception_type));
+        }
+
+        ServiceCalendar {
+            calendar,
+            exceptions,
+        }
+    }
+
+    /// Service ids of the services running on ```date```.
+    ///
+    /// A service runs if its weekly pattern includes the day of the week and ```date``` is within
+    /// its start and end dates, unless removed for ```date``` by calendar_dates.txt. Services added
+    /// for ```date``` by calendar_dates.txt run regardless of calendar.txt.
+    pub fn services_on(&self, date: NaiveDate) -> HashSet<String> {
+        let mut services: HashSet<String> = self
+            .calendar
+            .iter()
+            .filter(|calendar| runs_weekly_on(calendar, date))
+            .map(|calendar| calendar.service_id.clone())
+            .collect();
+
+        for (service_id, exception_type) in self.exceptions.get(&date).into_iter().flatten() {
+            match exception_type {
+                ExceptionType::Added => {
+                    services.insert(service_id.clone());
+                }
+                ExceptionType::Removed => {
+                    services.remove(service_id);
+                }
+            }
+        }
+        services
+    }
+
+    /// Whether the service ```service_id``` runs on ```date```.
+    pub fn is_active(&self, service_id: &str, date: NaiveDate) -> bool {
+        let exception = self.exceptions.get(&date).and_then(|exceptions| {
+            exceptions
+                .iter()
+                .find(|(id, _)| id == service_id)
+                .map(|(_, exception_type)| *exception_type)
+        });
+
+        match exception {
+            Some(ExceptionType::Added) => true,
+            Some(ExceptionType::Removed) => false,
+            None => self.calendar.iter().any(|calendar| {
+                calendar.service_id == service_id && runs_weekly_on(calendar, date)
+            }),
+        }
+    }
+}
+
+/// Start of the service day ```date``` in the timezone ```tz```, to which GTFS times are relative.
+///
+/// This is "noon minus 12h" rather than midnight, so that times remain correct on days with
+/// daylight saving changes.
+pub fn service_day_start<T: TimeZone>(date: NaiveDate, tz: &T) -> DateTime<T> {
+    let noon = date.and_hms_opt(12, 0, 0).unwrap();
+    let noon = tz
+        .from_local_datetime(&noon)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_datetime(&noon));
+    noon - Duration::hours(12)
+}
+
+/// Whether the weekly pattern of ```calendar``` includes ```date```, ignoring calendar_dates.txt.
+fn runs_weekly_on(calendar: &Calendar, date: NaiveDate) -> bool {
+    if date < calendar.start_date || date > calendar.end_date {
+        return false;
+    }
+
+    let runs = match date.weekday() {
+        Weekday::Mon => calendar.monday,
+        Weekday::Tue => calendar.tuesday,
+        Weekday::Wed => calendar.wednesday,
+        Weekday::Thu => calendar.thursday,
+        Weekday::Fri => calendar.friday,
+        Weekday::Sat => calendar.saturday,
+        Weekday::Sun => calendar.sunday,
+    };
+    runs == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_static::test_feed;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn services(calendar: &ServiceCalendar, date: NaiveDate) -> Vec<String> {
+        let mut services: Vec<String> = calendar.services_on(date).into_iter().collect();
+        services.sort();
+        services
+    }
+
+    #[test]
+    fn resolve_services() {
+        let feed = test_feed::feed();
+        let calendar = ServiceCalendar::new(feed.calendar, feed.calendar_dates);
+
+        // Tuesday, Saturday and outside of the calendar date range.
+        assert_eq!(services(&calendar, date(2021, 8, 10)), vec!["WEEKDAY"]);
+        assert_eq!(services(&calendar, date(2021, 8, 14)), vec!["WEEKEND"]);
+        assert!(services(&calendar, date(2022, 1, 3)).is_empty());
+
+        // Ekka holiday replaces WEEKDAY with WEEKEND, SPECIAL is only in calendar_dates.txt.
+        assert_eq!(services(&calendar, date(2021, 8, 11)), vec!["WEEKEND"]);
+        assert_eq!(
+            services(&calendar, date(2021, 8, 12)),
+            vec!["SPECIAL", "WEEKDAY"]
+        );
+
+        assert!(calendar.is_active("SPECIAL", date(2021, 8, 12)));
+        assert!(!calendar.is_active("SPECIAL", date(2021, 8, 13)));
+        assert!(!calendar.is_active("WEEKDAY", date(2021, 8, 11)));
+    }
+}
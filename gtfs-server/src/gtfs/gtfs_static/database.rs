@@ -0,0 +1,134 @@
+//! GTFS-static queries on a database generated by ```generate_database``` (Postgres) or
+//! ```generate_sqlite_database``` (SQLite, with the ```sqlite``` feature).
+
+use crate::gtfs::gtfs_static::models::*;
+use crate::gtfs::gtfs_static::schema::*;
+use crate::gtfs::gtfs_static::{connect, establish_connection, GtfsStatic, GtfsStaticError};
+use diesel::prelude::*;
+use std::sync::{Mutex, MutexGuard};
+
+/// Static feed stored in a database, queried through the shared diesel schema.
+pub struct DatabaseStatic<C: Connection> {
+    conn: Mutex<C>,
+}
+
+/// Static feed stored in a Postgres database.
+pub type PostgresStatic = DatabaseStatic<PgConnection>;
+
+/// Static feed stored in a single SQLite database file.
+#[cfg(feature = "sqlite")]
+pub type SqliteStatic = DatabaseStatic<SqliteConnection>;
+
+impl<C: Connection> DatabaseStatic<C> {
+    /// Connect to the database at ```database_url``` (a file path for SQLite).
+    pub fn new(database_url: &str) -> Result<Self, GtfsStaticError> {
+        Ok(DatabaseStatic {
+            conn: Mutex::new(connect(database_url)?),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, C> {
+        // a panic while querying cannot leave the connection itself in an invalid state
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl PostgresStatic {
+    /// Connect to the database given by the ```DATABASE_URL``` environment variable (or .env file).
+    pub fn from_env() -> Result<Self, GtfsStaticError> {
+        Ok(DatabaseStatic {
+            conn: Mutex::new(establish_connection()?),
+        })
+    }
+}
+
+/// Implement ```GtfsStatic``` for a database backend. The queries are identical for every
+/// backend, but diesel requires the connection type to be known to build them.
+macro_rules! impl_gtfs_static {
+    ($connection:ty) => {
+        impl GtfsStatic for DatabaseStatic<$connection> {
+            fn agencies(&self) -> Result<Vec<Agency>, GtfsStaticError> {
+                Ok(agency::table.load(&*self.conn())?)
+            }
+
+            fn stop(&self, stop_id: &str) -> Result<Option<Stop>, GtfsStaticError> {
+                Ok(stops::table.find(stop_id).first(&*self.conn()).optional()?)
+            }
+
+            fn route(&self, route_id: &str) -> Result<Option<Route>, GtfsStaticError> {
+                Ok(routes::table
+                    .find(route_id)
+                    .first(&*self.conn())
+                    .optional()?)
+            }
+
+            fn trip(&self, trip_id: &str) -> Result<Option<Trip>, GtfsStaticError> {
+                Ok(trips::table.find(trip_id).first(&*self.conn()).optional()?)
+            }
+
+            fn shape(&self, shape_id: &str) -> Result<Vec<Shape>, GtfsStaticError> {
+                Ok(shapes::table
+                    .filter(shapes::shape_id.eq(shape_id))
+                    .order(shapes::shape_pt_sequence)
+                    .load(&*self.conn())?)
+            }
+
+            fn stop_times_for_trip(&self, trip_id: &str) -> Result<Vec<StopTime>, GtfsStaticError> {
+                Ok(stop_times::table
+                    .filter(stop_times::trip_id.eq(trip_id))
+                    .order(stop_times::stop_sequence)
+                    .load(&*self.conn())?)
+            }
+
+            fn stop_times_at_stops(
+                &self,
+                stop_ids: &[String],
+            ) -> Result<Vec<StopTime>, GtfsStaticError> {
+                Ok(stop_times::table
+                    .filter(stop_times::stop_id.eq_any(stop_ids))
+                    .load(&*self.conn())?)
+            }
+
+            fn calendar(&self) -> Result<Vec<Calendar>, GtfsStaticError> {
+                Ok(calendar::table.load(&*self.conn())?)
+            }
+
+            fn calendar_dates(&self) -> Result<Vec<CalendarDate>, GtfsStaticError> {
+                Ok(calendar_dates::table.load(&*self.conn())?)
+            }
+        }
+    };
+}
+
+impl_gtfs_static!(PgConnection);
+
+#[cfg(feature = "sqlite")]
+impl_gtfs_static!(SqliteConnection);
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_static::{generate_sqlite_database, test_feed};
+
+    #[test]
+    fn query_sqlite_feed() {
+        let dir = test_feed::write_feed();
+        let database_path = format!("{:}/gtfs.sqlite", dir);
+        generate_sqlite_database(&dir, &database_path).unwrap();
+        // regenerating replaces the existing contents
+        generate_sqlite_database(&dir, &database_path).unwrap();
+
+        let feed = SqliteStatic::new(&database_path).unwrap();
+        let stop_times = feed.stop_times_for_trip("T4").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            feed.stop("600029").unwrap().unwrap().stop_name,
+            "Roma Street station, platform 4"
+        );
+        assert_eq!(stop_times.len(), 3);
+        assert_eq!(stop_times[2].arrival_time.unwrap().to_string(), "24:20:00");
+        assert_eq!(feed.calendar_dates().unwrap().len(), 3);
+        assert_eq!(feed.shape("BUZ-CITY").unwrap()[1].shape_pt_sequence, 2);
+    }
+}
@@ -0,0 +1,187 @@
+//! Scheduled departures from a set of stops, as shown on a departure board.
+
+use crate::gtfs::gtfs_static::calendar::service_day_start;
+use crate::gtfs::gtfs_static::models::{Route, Trip};
+use crate::gtfs::gtfs_static::types::{GtfsTime, PickupDropOffType};
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use chrono::{DateTime, Duration, NaiveDate};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+/// Departure of a trip from a stop according to the static timetable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledDeparture {
+    pub trip_id: String,
+    pub route_id: String,
+    pub route_short_name: Option<String>,
+    pub trip_headsign: String,
+    pub direction_id: i32,
+    pub stop_id: String,
+    pub stop_sequence: i32,
+    /// Service day the trip runs on, which is the previous day for trips running past midnight.
+    pub service_date: NaiveDate,
+    /// Departure time relative to the start of the service day.
+    pub departure_time: GtfsTime,
+    pub departure: DateTime<Tz>,
+}
+
+/// Up to ```max_departures``` scheduled departures from any of ```stop_ids``` at or after
+/// ```time```, in order of departure, optionally only of trips in the direction
+/// ```direction_id```.
+///
+/// Trips are included from the service days before, of and after the local date of ```time```,
+/// so trips from the previous service day with times past 24:00:00 are included. Stop times
+/// without a departure time use the arrival time, and stops at which passengers cannot board are
+/// skipped.
+pub fn scheduled_departures<S: GtfsStatic + ?Sized>(
+    gtfs: &S,
+    stop_ids: &[String],
+    time: DateTime<Tz>,
+    max_departures: usize,
+    direction_id: Option<i32>,
+) -> Result<Vec<ScheduledDeparture>, GtfsStaticError> {
+    let calendar = gtfs.service_calendar()?;
+    let tz = time.timezone();
+    let today = time.naive_local().date();
+    let service_days: Vec<(NaiveDate, DateTime<Tz>)> = [-1, 0, 1]
+        .iter()
+        .map(|&days| today + Duration::days(days))
+        .map(|date| (date, service_day_start(date, &tz)))
+        .collect();
+
+    let mut trips: HashMap<String, Option<Trip>> = HashMap::new();
+    let mut routes: HashMap<String, Option<Route>> = HashMap::new();
+    let mut departures = Vec::new();
+
+    for stop_time in gtfs.stop_times_at_stops(stop_ids)? {
+        if stop_time.pickup_type == PickupDropOffType::NoneAvailable {
+            continue;
+        }
+        let departure_time = match stop_time.departure_time.or(stop_time.arrival_time) {
+            Some(departure_time) => departure_time,
+            None => continue,
+        };
+
+        if !trips.contains_key(&stop_time.trip_id) {
+            let trip = gtfs.trip(&stop_time.trip_id)?;
+            trips.insert(stop_time.trip_id.clone(), trip);
+        }
+        let trip = match &trips[&stop_time.trip_id] {
+            Some(trip) => trip,
+            None => continue,
+        };
+        if direction_id.is_some() && direction_id != Some(trip.direction_id) {
+            continue;
+        }
+
+        for (service_date, day_start) in service_days.iter() {
+            let departure = *day_start + Duration::seconds(departure_time.seconds().into());
+            if departure < time || !calendar.is_active(&trip.service_id, *service_date) {
+                continue;
+            }
+
+            if !routes.contains_key(&trip.route_id) {
+                let route = gtfs.route(&trip.route_id)?;
+                routes.insert(trip.route_id.clone(), route);
+            }
+            let route_short_name = routes[&trip.route_id]
+                .as_ref()
+                .and_then(|route| route.route_short_name.clone());
+
+            departures.push(ScheduledDeparture {
+                trip_id: trip.trip_id.clone(),
+                route_id: trip.route_id.clone(),
+                route_short_name,
+                trip_headsign: trip.trip_headsign.clone(),
+                direction_id: trip.direction_id,
+                stop_id: stop_time.stop_id.clone(),
+                stop_sequence: stop_time.stop_sequence,
+                service_date: *service_date,
+                departure_time,
+                departure,
+            });
+        }
+    }
+
+    departures.sort_by(|a, b| {
+        a.departure
+            .cmp(&b.departure)
+            .then_with(|| a.trip_id.cmp(&b.trip_id))
+    });
+    departures.truncate(max_departures);
+    Ok(departures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_static::memory::MemoryStatic;
+    use crate::gtfs::gtfs_static::test_feed;
+    use chrono::TimeZone;
+    use chrono_tz::Australia::Brisbane;
+
+    fn brisbane(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        let time = NaiveDate::from_ymd_opt(2021, 8, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap();
+        Brisbane.from_local_datetime(&time).unwrap()
+    }
+
+    fn trips(departures: &[ScheduledDeparture]) -> Vec<(&str, String)> {
+        departures
+            .iter()
+            .map(|departure| {
+                (
+                    departure.trip_id.as_str(),
+                    departure.departure.format("%d %H:%M").to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn departures_from_stop() {
+        let feed = MemoryStatic::from_feed(test_feed::feed());
+        let stops = [String::from("10795")];
+        // Tuesday
+        let time = brisbane(10, 8, 10);
+
+        let departures = scheduled_departures(&feed, &stops, time, 2, None).unwrap();
+        assert_eq!(
+            trips(&departures),
+            vec![
+                ("T1", String::from("10 08:15")),
+                ("T2", String::from("10 08:40"))
+            ]
+        );
+        assert_eq!(departures[0].route_short_name.as_deref(), Some("BUZ"));
+        assert_eq!(departures[0].trip_headsign, "City");
+        assert_eq!(departures[0].stop_sequence, 2);
+
+        let departures = scheduled_departures(&feed, &stops, time, 1, Some(1)).unwrap();
+        assert_eq!(trips(&departures), vec![("T2", String::from("10 08:40"))]);
+    }
+
+    #[test]
+    fn departures_past_midnight() {
+        let feed = MemoryStatic::from_feed(test_feed::feed());
+        let stops = [String::from("10795")];
+        // Wednesday (Ekka holiday, running the WEEKEND service) just after midnight
+        let time = brisbane(11, 0, 0);
+
+        let departures = scheduled_departures(&feed, &stops, time, 3, None).unwrap();
+        assert_eq!(
+            trips(&departures),
+            vec![
+                ("T4", String::from("11 00:05")),
+                ("T3", String::from("11 09:15")),
+                ("T1", String::from("12 08:15"))
+            ]
+        );
+        assert_eq!(
+            departures[0].service_date,
+            NaiveDate::from_ymd_opt(2021, 8, 10).unwrap()
+        );
+        assert_eq!(departures[0].departure_time.to_string(), "24:05:00");
+    }
+}
@@ -0,0 +1,444 @@
+//! Reading of GTFS-static files into the models stored in the static database.
+
+use crate::gtfs::gtfs_static::models::*;
+use crate::gtfs::gtfs_static::reader::{GtfsReader, Record};
+use crate::gtfs::gtfs_static::types::{LocationType, PickupDropOffType};
+use crate::gtfs::gtfs_static::GtfsStaticError;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Contents of a GTFS-static feed, read from the static files.
+pub struct StaticFeed {
+    pub agency: Vec<Agency>,
+    pub calendar: Vec<Calendar>,
+    pub calendar_dates: Vec<CalendarDate>,
+    pub fare_attributes: Vec<FareAttribute>,
+    pub fare_rules: Vec<FareRule>,
+    pub feed_info: Vec<FeedInfo>,
+    pub frequencies: Vec<Frequency>,
+    pub routes: Vec<Route>,
+    pub shapes: Vec<Shape>,
+    pub stops: Vec<Stop>,
+    pub stop_times: Vec<StopTime>,
+    pub transfers: Vec<Transfer>,
+    pub trips: Vec<Trip>,
+}
+
+impl StaticFeed {
+    /// Read every modelled file from either a zip archive or a directory of unzipped GTFS-static
+    /// files.
+    ///
+    /// agency.txt, routes.txt, stops.txt, stop_times.txt and trips.txt must be present. All other
+    /// files are optional, with calendar.txt and calendar_dates.txt each optional as a feed may
+    /// define its services using either (or both) of them.
+    pub fn from_path(static_file_path: &str) -> Result<Self, GtfsStaticError> {
+        let mut files = StaticFiles::open(static_file_path)?;
+
+        Ok(StaticFeed {
+            agency: files.read("agency.txt", parse_agency)?,
+            calendar: files.read_optional("calendar.txt", parse_calendar)?,
+            calendar_dates: files.read_optional("calendar_dates.txt", parse_calendar_date)?,
+            fare_attributes: files.read_optional("fare_attributes.txt", parse_fare_attribute)?,
+            fare_rules: files.read_optional("fare_rules.txt", parse_fare_rule)?,
+            feed_info: files.read_optional("feed_info.txt", parse_feed_info)?,
+            frequencies: files.read_optional("frequencies.txt", parse_frequency)?,
+            routes: files.read("routes.txt", parse_route)?,
+            shapes: files.read_optional("shapes.txt", parse_shape)?,
+            stops: files.read("stops.txt", parse_stop)?,
+            stop_times: files.read("stop_times.txt", parse_stop_time)?,
+            transfers: files.read_optional("transfers.txt", parse_transfer)?,
+            trips: files.read("trips.txt", parse_trip)?,
+        })
+    }
+}
+
+/// Details of a GTFS-static feed used to check its validity, read without importing the full feed.
+pub struct FeedValidity {
+    /// Last date the feed provides service for, from feed_info.txt (if provided).
+    pub feed_end_date: Option<NaiveDate>,
+    /// Timezone of the feed's agencies, from agency.txt (if provided).
+    pub timezone: Option<Tz>,
+}
+
+impl FeedValidity {
+    /// Read the feed validity details from either a zip archive or a directory of unzipped
+    /// GTFS-static files.
+    pub fn from_path(static_file_path: &str) -> Result<Self, GtfsStaticError> {
+        let mut files = StaticFiles::open(static_file_path)?;
+
+        let feed_end_dates = files.read_optional("feed_info.txt", |row| {
+            row.parse_date_optional("feed_end_date")
+        })?;
+        let timezones =
+            files.read_optional("agency.txt", |row| match row.get("agency_timezone") {
+                None => Ok(None),
+                Some(timezone) => match timezone.parse::<Tz>() {
+                    Ok(timezone) => Ok(Some(timezone)),
+                    Err(e) => Err(GtfsStaticError::ParseTimezoneError(
+                        row.location("agency_timezone"),
+                        e,
+                    )),
+                },
+            })?;
+
+        Ok(FeedValidity {
+            feed_end_date: feed_end_dates.into_iter().flatten().max(),
+            // all agencies within a feed must share the same timezone
+            timezone: timezones.into_iter().flatten().next(),
+        })
+    }
+}
+
+/// Location of the GTFS-static files, either unzipped into a directory or within a zip archive.
+/// Archive members are streamed directly from the archive without being extracted to disk.
+enum StaticFiles {
+    Directory(PathBuf),
+    Archive(String, ZipArchive<File>),
+}
+
+impl StaticFiles {
+    fn open(static_file_path: &str) -> Result<Self, GtfsStaticError> {
+        let path = Path::new(static_file_path);
+        if path.is_dir() {
+            return Ok(StaticFiles::Directory(path.to_path_buf()));
+        }
+
+        let archive = ZipArchive::new(File::open(path)?)
+            .map_err(|e| GtfsStaticError::ArchiveError(None, e))?;
+        let archive_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from(static_file_path));
+        Ok(StaticFiles::Archive(archive_name, archive))
+    }
+
+    /// Name of the archive member holding ```file_name```. Some feeds nest their files within a
+    /// directory inside the archive, so members are matched on their file name only.
+    fn archive_member(archive: &ZipArchive<File>, file_name: &str) -> Option<String> {
+        archive
+            .file_names()
+            .find(|member| member.rsplit('/').next() == Some(file_name))
+            .map(String::from)
+    }
+
+    fn contains(&self, file_name: &str) -> bool {
+        match self {
+            StaticFiles::Directory(dir) => dir.join(file_name).exists(),
+            StaticFiles::Archive(_, archive) => {
+                StaticFiles::archive_member(archive, file_name).is_some()
+            }
+        }
+    }
+
+    /// Read every record of a GTFS-static file, mapping each record with ```parse```.
+    fn read<T, F>(&mut self, file_name: &str, parse: F) -> Result<Vec<T>, GtfsStaticError>
+    where
+        F: Fn(&Record) -> Result<T, GtfsStaticError>,
+    {
+        if !self.contains(file_name) {
+            return Err(GtfsStaticError::MissingFile(String::from(file_name)));
+        }
+        match self {
+            StaticFiles::Directory(dir) => {
+                let file = File::open(dir.join(file_name))?;
+                GtfsReader::new(file_name, BufReader::new(file))?
+                    .map(|record| parse(&record?))
+                    .collect()
+            }
+            StaticFiles::Archive(archive_name, archive) => {
+                let member = StaticFiles::archive_member(archive, file_name)
+                    .ok_or_else(|| GtfsStaticError::MissingFile(String::from(file_name)))?;
+                let member_error = |e: std::io::Error| {
+                    GtfsStaticError::ArchiveError(Some(member.clone()), ZipError::Io(e))
+                };
+
+                let zip_file = archive
+                    .by_name(&member)
+                    .map_err(|e| GtfsStaticError::ArchiveError(Some(member.clone()), e))?;
+                let location_name = format!("{:}/{:}", archive_name, member);
+                GtfsReader::new(&location_name, BufReader::new(zip_file))
+                    .map_err(|e| match e {
+                        GtfsStaticError::StaticFileError(e) => member_error(e),
+                        e => e,
+                    })?
+                    .map(|record| match record {
+                        Ok(record) => parse(&record),
+                        Err(GtfsStaticError::StaticFileError(e)) => Err(member_error(e)),
+                        Err(e) => Err(e),
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Read a GTFS-static file that may not be present in the feed.
+    fn read_optional<T, F>(&mut self, file_name: &str, parse: F) -> Result<Vec<T>, GtfsStaticError>
+    where
+        F: Fn(&Record) -> Result<T, GtfsStaticError>,
+    {
+        if self.contains(file_name) {
+            self.read(file_name, parse)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn parse_agency(row: &Record) -> Result<Agency, GtfsStaticError> {
+    Ok(Agency {
+        // agency_id may be omitted from feeds with a single agency
+        agency_id: String::from(row.get("agency_id").unwrap_or_default()),
+        agency_name: String::from(row.required("agency_name")?),
+        agency_url: String::from(row.required("agency_url")?),
+        agency_timezone: String::from(row.required("agency_timezone")?),
+        agency_lang: row.get("agency_lang").map(String::from),
+        agency_phone: row.get("agency_phone").map(String::from),
+        agency_fare_url: row.get("agency_fare_url").map(String::from),
+        agency_email: row.get("agency_email").map(String::from),
+    })
+}
+
+fn parse_calendar(row: &Record) -> Result<Calendar, GtfsStaticError> {
+    Ok(Calendar {
+        service_id: String::from(row.required("service_id")?),
+        monday: row.parse("monday")?,
+        tuesday: row.parse("tuesday")?,
+        wednesday: row.parse("wednesday")?,
+        thursday: row.parse("thursday")?,
+        friday: row.parse("friday")?,
+        saturday: row.parse("saturday")?,
+        sunday: row.parse("sunday")?,
+        start_date: row.parse_date("start_date")?,
+        end_date: row.parse_date("end_date")?,
+    })
+}
+
+fn parse_calendar_date(row: &Record) -> Result<CalendarDate, GtfsStaticError> {
+    Ok(CalendarDate {
+        service_id: String::from(row.required("service_id")?),
+        date: row.parse_date("date")?,
+        exception_type: row.parse("exception_type")?,
+    })
+}
+
+fn parse_fare_attribute(row: &Record) -> Result<FareAttribute, GtfsStaticError> {
+    Ok(FareAttribute {
+        fare_id: String::from(row.required("fare_id")?),
+        price: row.parse("price")?,
+        currency_type: String::from(row.required("currency_type")?),
+        payment_method: row.parse("payment_method")?,
+        // an empty transfers field means unlimited transfers are permitted
+        transfers: row.parse_optional("transfers")?,
+        agency_id: row.get("agency_id").map(String::from),
+        transfer_duration: row.parse_optional("transfer_duration")?,
+    })
+}
+
+fn parse_fare_rule(row: &Record) -> Result<FareRule, GtfsStaticError> {
+    Ok(FareRule {
+        fare_id: String::from(row.required("fare_id")?),
+        route_id: String::from(row.get("route_id").unwrap_or_default()),
+        origin_id: String::from(row.get("origin_id").unwrap_or_default()),
+        destination_id: String::from(row.get("destination_id").unwrap_or_default()),
+        contains_id: String::from(row.get("contains_id").unwrap_or_default()),
+    })
+}
+
+fn parse_feed_info(row: &Record) -> Result<FeedInfo, GtfsStaticError> {
+    Ok(FeedInfo {
+        feed_publisher_name: String::from(row.required("feed_publisher_name")?),
+        feed_publisher_url: String::from(row.required("feed_publisher_url")?),
+        feed_lang: String::from(row.required("feed_lang")?),
+        feed_start_date: row.parse_date_optional("feed_start_date")?,
+        feed_end_date: row.parse_date_optional("feed_end_date")?,
+        feed_version: row.get("feed_version").map(String::from),
+        feed_contact_email: row.get("feed_contact_email").map(String::from),
+        feed_contact_url: row.get("feed_contact_url").map(String::from),
+    })
+}
+
+fn parse_frequency(row: &Record) -> Result<Frequency, GtfsStaticError> {
+    Ok(Frequency {
+        trip_id: String::from(row.required("trip_id")?),
+        start_time: row.parse("start_time")?,
+        end_time: row.parse("end_time")?,
+        headway_secs: row.parse("headway_secs")?,
+        exact_times: row.parse_or("exact_times", 0)?,
+    })
+}
+
+fn parse_route(row: &Record) -> Result<Route, GtfsStaticError> {
+    Ok(Route {
+        route_id: String::from(row.required("route_id")?),
+        route_short_name: row.get("route_short_name").map(String::from),
+        route_long_name: row.get("route_long_name").map(String::from),
+        route_desc: row.get("route_desc").map(String::from),
+        route_type: row.parse("route_type")?,
+        route_url: String::from(row.get("route_url").unwrap_or_default()),
+        route_color: String::from(row.get("route_color").unwrap_or("FFFFFF")),
+        route_text_color: String::from(row.get("route_text_color").unwrap_or("000000")),
+    })
+}
+
+fn parse_shape(row: &Record) -> Result<Shape, GtfsStaticError> {
+    Ok(Shape {
+        shape_id: String::from(row.required("shape_id")?),
+        shape_pt_lat: row.parse("shape_pt_lat")?,
+        shape_pt_lon: row.parse("shape_pt_lon")?,
+        shape_pt_sequence: row.parse("shape_pt_sequence")?,
+        shape_dist_traveled: row.parse_optional("shape_dist_traveled")?,
+    })
+}
+
+fn parse_stop_time(row: &Record) -> Result<StopTime, GtfsStaticError> {
+    Ok(StopTime {
+        trip_id: String::from(row.required("trip_id")?),
+        // times may be omitted for stops which are not timepoints
+        arrival_time: row.parse_optional("arrival_time")?,
+        departure_time: row.parse_optional("departure_time")?,
+        stop_id: String::from(row.required("stop_id")?),
+        stop_sequence: row.parse("stop_sequence")?,
+        pickup_type: row.parse_or("pickup_type", PickupDropOffType::Regular)?,
+        drop_off_type: row.parse_or("drop_off_type", PickupDropOffType::Regular)?,
+    })
+}
+
+fn parse_stop(row: &Record) -> Result<Stop, GtfsStaticError> {
+    Ok(Stop {
+        stop_id: String::from(row.required("stop_id")?),
+        stop_code: row.get("stop_code").map(String::from),
+        stop_name: String::from(row.required("stop_name")?),
+        stop_desc: row.get("stop_desc").map(String::from),
+        stop_lat: row.parse("stop_lat")?,
+        stop_lon: row.parse("stop_lon")?,
+        zone_id: row.get("zone_id").map(String::from),
+        stop_url: row.get("stop_url").map(String::from),
+        location_type: row.parse_or("location_type", LocationType::Stop)?,
+        parent_station: row.get("parent_station").map(String::from),
+        platform_code: row.get("platform_code").map(String::from),
+    })
+}
+
+fn parse_transfer(row: &Record) -> Result<Transfer, GtfsStaticError> {
+    Ok(Transfer {
+        from_stop_id: String::from(row.required("from_stop_id")?),
+        to_stop_id: String::from(row.required("to_stop_id")?),
+        transfer_type: row.parse_or("transfer_type", 0)?,
+        min_transfer_time: row.parse_optional("min_transfer_time")?,
+    })
+}
+
+fn parse_trip(row: &Record) -> Result<Trip, GtfsStaticError> {
+    Ok(Trip {
+        route_id: String::from(row.required("route_id")?),
+        service_id: String::from(row.required("service_id")?),
+        trip_id: String::from(row.required("trip_id")?),
+        trip_headsign: String::from(row.get("trip_headsign").unwrap_or_default()),
+        direction_id: row.parse_or("direction_id", 0)?,
+        block_id: row.get("block_id").map(String::from),
+        shape_id: row.get("shape_id").map(String::from),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const MEMBERS: [(&str, &str); 6] = [
+        (
+            "agency.txt",
+            "agency_name,agency_url,agency_timezone\n\
+             TransLink,https://translink.com.au,Australia/Brisbane\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+             WEEKDAY,1,1,1,1,1,0,0,20210701,20211231\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,route_short_name,route_long_name,route_type\n\
+             66-1,66,UQ Lakes - RBWH Busway Station,3\n",
+        ),
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon\n\
+             1882,\"UQ Lakes station, platform C\",-27.497,153.017\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             T1,08:00:00,08:00:00,1882,1\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id,trip_headsign\n66-1,WEEKDAY,T1,RBWH\n",
+        ),
+    ];
+
+    fn write_archive(name: &str, members: &[(&str, &str)]) -> String {
+        let path =
+            std::env::temp_dir().join(format!("gtfs_server_{:}_{:}.zip", name, std::process::id()));
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (member, contents) in members {
+            zip.start_file(format!("SEQ_GTFS/{:}", member), FileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn read_zip_archive() {
+        let path = write_archive("read_zip_archive", &MEMBERS);
+        let feed = StaticFeed::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(feed.agency[0].agency_id, "");
+        assert_eq!(feed.calendar.len(), 1);
+        assert!(feed.calendar_dates.is_empty());
+        assert!(feed.shapes.is_empty());
+        assert_eq!(feed.stops[0].stop_name, "UQ Lakes station, platform C");
+        assert_eq!(feed.trips[0].trip_headsign, "RBWH");
+    }
+
+    #[test]
+    fn missing_archive_member() {
+        let path = write_archive("missing_archive_member", &MEMBERS[..5]);
+        let result = StaticFeed::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(GtfsStaticError::MissingFile(file_name)) => assert_eq!(file_name, "trips.txt"),
+            _ => panic!("expected missing trips.txt"),
+        }
+    }
+
+    #[test]
+    fn missing_directory_file() {
+        let dir = std::env::temp_dir().join(format!(
+            "gtfs_server_missing_directory_file_{:}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in MEMBERS[..5].iter() {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let result = StaticFeed::from_path(&dir.to_string_lossy());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(GtfsStaticError::MissingFile(file_name)) => assert_eq!(file_name, "trips.txt"),
+            _ => panic!("expected missing trips.txt"),
+        }
+    }
+}
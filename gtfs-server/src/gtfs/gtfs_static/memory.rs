@@ -0,0 +1,182 @@
+//! GTFS-static queries on a feed held entirely in memory, loaded straight from the static files.
+//! Suited to small deployments where running a database is not worthwhile, and to testing.
+
+use crate::gtfs::gtfs_static::import::StaticFeed;
+use crate::gtfs::gtfs_static::models::*;
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Static feed held in memory, indexed for the queries used by the server.
+pub struct MemoryStatic {
+    agencies: Vec<Agency>,
+    calendar: Vec<Calendar>,
+    calendar_dates: Vec<CalendarDate>,
+    routes: HashMap<String, Route>,
+    stops: HashMap<String, Stop>,
+    trips: HashMap<String, Trip>,
+    /// Points of each shape, ordered by sequence.
+    shapes: HashMap<String, Vec<Shape>>,
+    /// Stop times ordered by trip and stop sequence.
+    stop_times: Vec<StopTime>,
+    stop_times_by_trip: HashMap<String, Range<usize>>,
+    stop_times_by_stop: HashMap<String, Vec<usize>>,
+}
+
+impl MemoryStatic {
+    /// Load the feed from either the GTFS-static zip file or a directory of unzipped files.
+    pub fn from_path(static_file_path: &str) -> Result<Self, GtfsStaticError> {
+        Ok(MemoryStatic::from_feed(StaticFeed::from_path(
+            static_file_path,
+        )?))
+    }
+
+    /// Index an already read feed.
+    pub fn from_feed(feed: StaticFeed) -> Self {
+        let mut stop_times = feed.stop_times;
+        stop_times.sort_by(|a, b| {
+            a.trip_id
+                .cmp(&b.trip_id)
+                .then(a.stop_sequence.cmp(&b.stop_sequence))
+        });
+
+        let mut stop_times_by_trip: HashMap<String, Range<usize>> = HashMap::new();
+        let mut stop_times_by_stop: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, stop_time) in stop_times.iter().enumerate() {
+            stop_times_by_trip
+                .entry(stop_time.trip_id.clone())
+                .or_insert(i..i)
+                .end = i + 1;
+            stop_times_by_stop
+                .entry(stop_time.stop_id.clone())
+                .or_default()
+                .push(i);
+        }
+
+        let mut shapes: HashMap<String, Vec<Shape>> = HashMap::new();
+        for point in feed.shapes {
+            shapes
+                .entry(point.shape_id.clone())
+                .or_default()
+                .push(point);
+        }
+        for points in shapes.values_mut() {
+            points.sort_by_key(|point| point.shape_pt_sequence);
+        }
+
+        MemoryStatic {
+            agencies: feed.agency,
+            calendar: feed.calendar,
+            calendar_dates: feed.calendar_dates,
+            routes: feed
+                .routes
+                .into_iter()
+                .map(|route| (route.route_id.clone(), route))
+                .collect(),
+            stops: feed
+                .stops
+                .into_iter()
+                .map(|stop| (stop.stop_id.clone(), stop))
+                .collect(),
+            trips: feed
+                .trips
+                .into_iter()
+                .map(|trip| (trip.trip_id.clone(), trip))
+                .collect(),
+            shapes,
+            stop_times,
+            stop_times_by_trip,
+            stop_times_by_stop,
+        }
+    }
+}
+
+impl GtfsStatic for MemoryStatic {
+    fn agencies(&self) -> Result<Vec<Agency>, GtfsStaticError> {
+        Ok(self.agencies.clone())
+    }
+
+    fn stop(&self, stop_id: &str) -> Result<Option<Stop>, GtfsStaticError> {
+        Ok(self.stops.get(stop_id).cloned())
+    }
+
+    fn route(&self, route_id: &str) -> Result<Option<Route>, GtfsStaticError> {
+        Ok(self.routes.get(route_id).cloned())
+    }
+
+    fn trip(&self, trip_id: &str) -> Result<Option<Trip>, GtfsStaticError> {
+        Ok(self.trips.get(trip_id).cloned())
+    }
+
+    fn shape(&self, shape_id: &str) -> Result<Vec<Shape>, GtfsStaticError> {
+        Ok(self.shapes.get(shape_id).cloned().unwrap_or_default())
+    }
+
+    fn stop_times_for_trip(&self, trip_id: &str) -> Result<Vec<StopTime>, GtfsStaticError> {
+        Ok(match self.stop_times_by_trip.get(trip_id) {
+            Some(range) => self.stop_times[range.clone()].to_vec(),
+            None => Vec::new(),
+        })
+    }
+
+    fn stop_times_at_stops(&self, stop_ids: &[String]) -> Result<Vec<StopTime>, GtfsStaticError> {
+        // each stop once, as when querying the database
+        let stop_ids: HashSet<&String> = stop_ids.iter().collect();
+        Ok(stop_ids
+            .into_iter()
+            .filter_map(|stop_id| self.stop_times_by_stop.get(stop_id))
+            .flatten()
+            .map(|&i| self.stop_times[i].clone())
+            .collect())
+    }
+
+    fn calendar(&self) -> Result<Vec<Calendar>, GtfsStaticError> {
+        Ok(self.calendar.clone())
+    }
+
+    fn calendar_dates(&self) -> Result<Vec<CalendarDate>, GtfsStaticError> {
+        Ok(self.calendar_dates.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_static::test_feed;
+
+    #[test]
+    fn query_memory_feed() {
+        let feed = MemoryStatic::from_feed(test_feed::feed());
+
+        assert_eq!(
+            feed.stop("600029").unwrap().unwrap().stop_name,
+            "Roma Street station, platform 4"
+        );
+        assert!(feed.stop("missing").unwrap().is_none());
+        assert_eq!(
+            feed.route("BUZ-1").unwrap().unwrap().route_short_name,
+            Some(String::from("BUZ"))
+        );
+
+        let sequence: Vec<i32> = feed
+            .stop_times_for_trip("T1")
+            .unwrap()
+            .iter()
+            .map(|stop_time| stop_time.stop_sequence)
+            .collect();
+        assert_eq!(sequence, vec![1, 2, 3]);
+        assert_eq!(feed.shape("BUZ-CITY").unwrap().len(), 5);
+        assert!(feed.shape("missing").unwrap().is_empty());
+
+        let at_stop = feed.stop_times_at_stops(&[String::from("600029")]).unwrap();
+        assert!(at_stop
+            .iter()
+            .all(|stop_time| stop_time.stop_id == "600029"));
+        assert!(!at_stop.is_empty());
+        let repeated = [String::from("600029"), String::from("600029")];
+        assert_eq!(
+            feed.stop_times_at_stops(&repeated).unwrap().len(),
+            at_stop.len()
+        );
+    }
+}
@@ -1,25 +1,49 @@
 // GTFS static manager, maintaining and validating the static database and querying the database
 
+pub mod calendar;
+pub mod database;
+pub mod departures;
+pub mod import;
+pub mod memory;
 pub mod models;
+pub mod reader;
 pub mod schema;
+#[cfg(test)]
+pub(crate) mod test_feed;
+pub mod types;
 
-use crate::gtfs::gtfs_static::models::Calendar;
+use crate::gtfs::gtfs_static::calendar::ServiceCalendar;
+use crate::gtfs::gtfs_static::import::{FeedValidity, StaticFeed};
+use crate::gtfs::gtfs_static::models::*;
+use crate::gtfs::gtfs_static::reader::FileLocation;
+use crate::gtfs::gtfs_static::types::ParseValueError;
 use chrono::prelude::*;
+#[cfg(feature = "sqlite")]
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
 use dotenv::dotenv;
 use std::env;
-use std::io::{BufRead, BufReader, Error};
-use std::num::ParseIntError;
+use std::io::Error;
 
 /// GTFS-static associated errors.
 #[derive(Debug)]
 pub enum GtfsStaticError {
-    ExpiredDataset(Date<Local>),
+    ExpiredDataset(NaiveDate, NaiveDate),
     MissingDatabase,
+    MissingDatabaseUrl,
     StaticFileError(std::io::Error),
-    ParseIntError(std::num::ParseIntError),
-    ParseNoneError,
+    /// A required file, named by the value, is missing from the feed.
+    MissingFile(String),
+    ParseIntError(FileLocation, std::num::ParseIntError),
+    ParseFloatError(FileLocation, std::num::ParseFloatError),
+    ParseDateError(FileLocation, chrono::ParseError),
+    ParseTimezoneError(FileLocation, String),
+    ParseValueError(FileLocation, ParseValueError),
+    ParseNoneError(FileLocation),
+    UnterminatedQuote(FileLocation),
     DatabaseConnectionError(diesel::ConnectionError),
+    DatabaseError(diesel::result::Error),
+    ArchiveError(Option<String>, zip::result::ZipError),
 }
 
 impl std::error::Error for GtfsStaticError {}
@@ -28,20 +52,58 @@ impl std::fmt::Display for GtfsStaticError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use GtfsStaticError::*;
         match self {
-            ExpiredDataset(expiry_date) => {
-                let now = Local::now().date();
-                write!(
-                    f,
-                    "Static database expired {:}, currently {:}",
-                    expiry_date.to_string(),
-                    now.to_string()
-                )
-            }
+            ExpiredDataset(expiry_date, today) => write!(
+                f,
+                "Static database expired {:}, currently {:}",
+                expiry_date, today
+            ),
             MissingDatabase => write!(f, "Could not find database!"),
+            MissingDatabaseUrl => write!(f, "DATABASE_URL must be set to connect to database!"),
             StaticFileError(io_error) => write!(f, "Error with static file!\n{:}", io_error),
-            ParseIntError(err) => write!(f, "Unable to parse GTFS-static file!\n{:}", err),
-            ParseNoneError => write!(f, "Unable to parse GTFS-static file!\nParsed None"),
+            MissingFile(file_name) => write!(f, "Missing static file \"{:}\"!", file_name),
+            ParseIntError(location, err) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\n{:}",
+                location, err
+            ),
+            ParseFloatError(location, err) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\n{:}",
+                location, err
+            ),
+            ParseDateError(location, err) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\n{:}",
+                location, err
+            ),
+            ParseTimezoneError(location, err) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\n{:}",
+                location, err
+            ),
+            ParseValueError(location, err) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\n{:}",
+                location, err
+            ),
+            ParseNoneError(location) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\nMissing required field",
+                location
+            ),
+            UnterminatedQuote(location) => write!(
+                f,
+                "Unable to parse GTFS-static file at {:}!\nUnterminated quoted field",
+                location
+            ),
             DatabaseConnectionError(err) => write!(f, "Unable to connect to database!\n{:}", err),
+            DatabaseError(err) => write!(f, "Error querying database!\n{:}", err),
+            ArchiveError(None, err) => write!(f, "Error with static zip archive!\n{:}", err),
+            ArchiveError(Some(member), err) => write!(
+                f,
+                "Error with static zip archive member \"{:}\"!\n{:}",
+                member, err
+            ),
         }
     }
 }
@@ -52,9 +114,9 @@ impl From<std::io::Error> for GtfsStaticError {
     }
 }
 
-impl From<ParseIntError> for GtfsStaticError {
-    fn from(e: ParseIntError) -> Self {
-        GtfsStaticError::ParseIntError(e)
+impl From<diesel::result::Error> for GtfsStaticError {
+    fn from(e: diesel::result::Error) -> Self {
+        GtfsStaticError::DatabaseError(e)
     }
 }
 
@@ -62,64 +124,198 @@ impl From<ParseIntError> for GtfsStaticError {
 //
 // }
 
+/// Queries on a GTFS-static feed used by the server, independent of how the feed is stored.
+pub trait GtfsStatic: Send + Sync {
+    fn agencies(&self) -> Result<Vec<Agency>, GtfsStaticError>;
+
+    fn stop(&self, stop_id: &str) -> Result<Option<Stop>, GtfsStaticError>;
+
+    fn route(&self, route_id: &str) -> Result<Option<Route>, GtfsStaticError>;
+
+    fn trip(&self, trip_id: &str) -> Result<Option<Trip>, GtfsStaticError>;
+
+    /// Points of a shape, ordered by sequence.
+    fn shape(&self, shape_id: &str) -> Result<Vec<Shape>, GtfsStaticError>;
+
+    /// Stop times of a trip, ordered by stop sequence.
+    fn stop_times_for_trip(&self, trip_id: &str) -> Result<Vec<StopTime>, GtfsStaticError>;
+
+    /// Stop times of every trip serving any of ```stop_ids```, in no particular order.
+    fn stop_times_at_stops(&self, stop_ids: &[String]) -> Result<Vec<StopTime>, GtfsStaticError>;
+
+    fn calendar(&self) -> Result<Vec<Calendar>, GtfsStaticError>;
+
+    fn calendar_dates(&self) -> Result<Vec<CalendarDate>, GtfsStaticError>;
+
+    /// Service calendar combining calendar.txt and calendar_dates.txt, to determine which
+    /// services run on a given date.
+    fn service_calendar(&self) -> Result<ServiceCalendar, GtfsStaticError> {
+        Ok(ServiceCalendar::new(
+            self.calendar()?,
+            self.calendar_dates()?,
+        ))
+    }
+}
+
 /// Validate the current static database.
 ///     - Ensure the static database has not expired.
 ///     - Ensure a connection can be established to the static database.
+///     - Ensure the static database has been populated.
 /// If the static database cannot be validated for any of these reasons, an appropriate error is
 /// returned. Users may then wish to respond to these errors by downloading fresh static data and/or
 /// regenerating the database.
+///
+/// The expiry date is the feed_end_date from feed_info.txt, or the last date of service in the
+/// database if the feed does not provide one, and is compared against the current date in the
+/// agency's timezone.
 pub fn validate_static_database(
     static_file_path: &str,
     static_database_path: &str,
 ) -> Result<(), GtfsStaticError> {
-    // check current time on static files and compare it to expiry
-    // if expired, download new files and regenerate database.
-    Ok(())
+    use crate::gtfs::gtfs_static::schema::*;
+
+    let conn = connect::<PgConnection>(static_database_path)?;
+
+    let service_count = calendar::table.count().get_result::<i64>(&conn)?
+        + calendar_dates::table.count().get_result::<i64>(&conn)?;
+    let table_counts = [
+        service_count,
+        routes::table.count().get_result::<i64>(&conn)?,
+        stops::table.count().get_result::<i64>(&conn)?,
+        stop_times::table.count().get_result::<i64>(&conn)?,
+        trips::table.count().get_result::<i64>(&conn)?,
+    ];
+    if table_counts.contains(&0) {
+        return Err(GtfsStaticError::MissingDatabase);
+    }
+
+    let validity = FeedValidity::from_path(static_file_path)?;
+    let expiry_date = match validity.feed_end_date {
+        Some(date) => date,
+        None => {
+            let calendar_end = calendar::table
+                .select(diesel::dsl::max(calendar::end_date))
+                .first::<Option<NaiveDate>>(&conn)?;
+            let calendar_dates_end = calendar_dates::table
+                .select(diesel::dsl::max(calendar_dates::date))
+                .first::<Option<NaiveDate>>(&conn)?;
+            calendar_end
+                .max(calendar_dates_end)
+                .ok_or(GtfsStaticError::MissingDatabase)?
+        }
+    };
+
+    let today = match validity.timezone {
+        Some(timezone) => Utc::now().with_timezone(&timezone).naive_local().date(),
+        None => Local::now().naive_local().date(),
+    };
+
+    if today > expiry_date {
+        Err(GtfsStaticError::ExpiredDataset(expiry_date, today))
+    } else {
+        Ok(())
+    }
+}
+
+/// Clear every table of the database connection ```$conn``` and insert the contents of the
+/// ```StaticFeed``` ```$feed```, within a single transaction, for any database backend.
+macro_rules! populate_database {
+    ($conn:expr, $feed:expr) => {{
+        use crate::gtfs::gtfs_static::schema::*;
+
+        let conn = $conn;
+        let feed = $feed;
+
+        // Postgres limits a single statement to 65535 bind parameters, so rows are inserted in
+        // batches small enough for the widest table (stops, 11 columns).
+        macro_rules! insert_batches {
+            ($table:expr, $rows:expr) => {
+                for chunk in $rows.chunks(INSERT_BATCH_SIZE) {
+                    diesel::insert_into($table).values(chunk).execute(conn)?;
+                }
+            };
+        }
+
+        conn.transaction::<_, GtfsStaticError, _>(|| {
+            diesel::delete(agency::table).execute(conn)?;
+            diesel::delete(calendar::table).execute(conn)?;
+            diesel::delete(calendar_dates::table).execute(conn)?;
+            diesel::delete(fare_attributes::table).execute(conn)?;
+            diesel::delete(fare_rules::table).execute(conn)?;
+            diesel::delete(feed_info::table).execute(conn)?;
+            diesel::delete(frequencies::table).execute(conn)?;
+            diesel::delete(routes::table).execute(conn)?;
+            diesel::delete(shapes::table).execute(conn)?;
+            diesel::delete(stops::table).execute(conn)?;
+            diesel::delete(stop_times::table).execute(conn)?;
+            diesel::delete(transfers::table).execute(conn)?;
+            diesel::delete(trips::table).execute(conn)?;
+
+            insert_batches!(agency::table, feed.agency);
+            insert_batches!(calendar::table, feed.calendar);
+            insert_batches!(calendar_dates::table, feed.calendar_dates);
+            insert_batches!(fare_attributes::table, feed.fare_attributes);
+            insert_batches!(fare_rules::table, feed.fare_rules);
+            insert_batches!(feed_info::table, feed.feed_info);
+            insert_batches!(frequencies::table, feed.frequencies);
+            insert_batches!(routes::table, feed.routes);
+            insert_batches!(shapes::table, feed.shapes);
+            insert_batches!(stops::table, feed.stops);
+            insert_batches!(stop_times::table, feed.stop_times);
+            insert_batches!(transfers::table, feed.transfers);
+            insert_batches!(trips::table, feed.trips);
+
+            Ok(())
+        })
+    }};
 }
 
-/// Rebuild the database from a directory containing (either the zip file or unzipped files),
+/// Rebuild the database from either the GTFS-static zip file or a directory of unzipped files,
 /// overwriting the old (if any) database completely.
 /// todo! could rename to .old?
+///
+/// All tables are cleared and repopulated within a single transaction, so a failed import leaves
+/// the previous database intact.
 pub fn generate_database(static_file_path: &str) -> Result<(), GtfsStaticError> {
-    use crate::gtfs::gtfs_static::schema::calendar::dsl::*;
-
+    let feed = StaticFeed::from_path(static_file_path)?;
     let conn = establish_connection()?;
 
-    let mut calendar_dir = String::from(static_file_path);
-    calendar_dir.push_str("f");
-    let calendar_path = std::path::Path::new(&calendar_dir);
-    let calendar_file = std::fs::File::open(calendar_path)?;
-    let reader = BufReader::new(calendar_file);
-
-    for line in reader.lines().skip(1) {
-        use GtfsStaticError::ParseNoneError;
-        let line = line?;
-
-        let mut split = line.split(",");
-        let entry = Calendar {
-            service_id: String::from(split.nth(0).ok_or_else(|| ParseNoneError)?),
-            monday: split.nth(1).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            tuesday: split.nth(2).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            wednesday: split.nth(3).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            thursday: split.nth(4).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            friday: split.nth(5).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            saturday: split.nth(6).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            sunday: split.nth(7).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            start_date: split.nth(8).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-            end_date: split.nth(9).ok_or_else(|| ParseNoneError)?.parse::<i32>()?,
-        };
-
+    populate_database!(&conn, feed)
+}
 
-    }
+/// Rebuild a SQLite database file at ```database_path``` from either the GTFS-static zip file or
+/// a directory of unzipped files, creating the file and its tables if they do not exist.
+///
+/// As with ```generate_database```, the tables are repopulated within a single transaction.
+#[cfg(feature = "sqlite")]
+pub fn generate_sqlite_database(
+    static_file_path: &str,
+    database_path: &str,
+) -> Result<(), GtfsStaticError> {
+    let feed = StaticFeed::from_path(static_file_path)?;
+    let conn = connect::<SqliteConnection>(database_path)?;
+    conn.batch_execute(SQLITE_SCHEMA)?;
 
-    Ok(())
+    populate_database!(&conn, feed)
 }
 
-/// Connect to the database.
-fn establish_connection() -> Result<PgConnection, GtfsStaticError> {
+/// Tables of the SQLite database, equivalent to the Postgres migrations.
+#[cfg(feature = "sqlite")]
+const SQLITE_SCHEMA: &str = include_str!("sqlite_schema.sql");
+
+/// Maximum number of rows inserted per statement when generating the database.
+const INSERT_BATCH_SIZE: usize = 5000;
+
+/// Connect to the database given by the ```DATABASE_URL``` environment variable (or .env file).
+pub(crate) fn establish_connection() -> Result<PgConnection, GtfsStaticError> {
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    match PgConnection::establish(&database_url) {
+    let database_url = env::var("DATABASE_URL").map_err(|_| GtfsStaticError::MissingDatabaseUrl)?;
+    connect(&database_url)
+}
+
+/// Connect to the database at ```database_url```.
+pub(crate) fn connect<C: Connection>(database_url: &str) -> Result<C, GtfsStaticError> {
+    match C::establish(database_url) {
         Ok(conn) => Ok(conn),
         Err(e) => Err(GtfsStaticError::DatabaseConnectionError(e)),
     }
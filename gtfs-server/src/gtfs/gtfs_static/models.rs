@@ -1,4 +1,22 @@
-#[derive(Queryable)]
+use crate::gtfs::gtfs_static::schema::*;
+use crate::gtfs::gtfs_static::types::*;
+use chrono::NaiveDate;
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "agency"]
+pub struct Agency {
+    pub agency_id: String,
+    pub agency_name: String,
+    pub agency_url: String,
+    pub agency_timezone: String,
+    pub agency_lang: Option<String>,
+    pub agency_phone: Option<String>,
+    pub agency_fare_url: Option<String>,
+    pub agency_email: Option<String>,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "calendar"]
 pub struct Calendar {
     pub service_id: String,
     pub monday: i32,
@@ -8,62 +26,132 @@ pub struct Calendar {
     pub friday: i32,
     pub saturday: i32,
     pub sunday: i32,
-    pub start_date: i32,
-    pub end_date: i32,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
 }
 
-#[derive(Queryable)]
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "calendar_dates"]
 pub struct CalendarDate {
-    service_id: String,
-    date: i32,
-    exception_type: i32,
+    pub service_id: String,
+    pub date: NaiveDate,
+    pub exception_type: ExceptionType,
 }
 
-#[derive(Queryable)]
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "fare_attributes"]
+pub struct FareAttribute {
+    pub fare_id: String,
+    pub price: f32,
+    pub currency_type: String,
+    pub payment_method: i32,
+    pub transfers: Option<i32>,
+    pub agency_id: Option<String>,
+    pub transfer_duration: Option<i32>,
+}
+
+/// Rule applying a fare to an itinerary. Columns not used by the rule are empty.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "fare_rules"]
+pub struct FareRule {
+    pub fare_id: String,
+    pub route_id: String,
+    pub origin_id: String,
+    pub destination_id: String,
+    pub contains_id: String,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "feed_info"]
+pub struct FeedInfo {
+    pub feed_publisher_name: String,
+    pub feed_publisher_url: String,
+    pub feed_lang: String,
+    pub feed_start_date: Option<NaiveDate>,
+    pub feed_end_date: Option<NaiveDate>,
+    pub feed_version: Option<String>,
+    pub feed_contact_email: Option<String>,
+    pub feed_contact_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "frequencies"]
+pub struct Frequency {
+    pub trip_id: String,
+    pub start_time: GtfsTime,
+    pub end_time: GtfsTime,
+    pub headway_secs: i32,
+    pub exact_times: i32,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "routes"]
 pub struct Route {
-    route_id: String,
-    route_short_name: i32,
-    route_long_name: String,
-    route_desc: Option<String>,
-    route_type: i32,
-    route_url: String,
-    route_color: String,
-    route_text_color: String,
+    pub route_id: String,
+    pub route_short_name: Option<String>,
+    pub route_long_name: Option<String>,
+    pub route_desc: Option<String>,
+    pub route_type: RouteType,
+    pub route_url: String,
+    pub route_color: String,
+    pub route_text_color: String,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "shapes"]
+pub struct Shape {
+    pub shape_id: String,
+    pub shape_pt_lat: f32,
+    pub shape_pt_lon: f32,
+    pub shape_pt_sequence: i32,
+    pub shape_dist_traveled: Option<f32>,
 }
 
-#[derive(Queryable)]
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "stop_times"]
 pub struct StopTime {
-    trip_id: String,
-    arrival_time: String,
-    departure_time: String,
-    stop_id: i32,
-    stop_sequence: i32,
-    pickup_type: i32,
-    drop_off_type: i32,
+    pub trip_id: String,
+    pub arrival_time: Option<GtfsTime>,
+    pub departure_time: Option<GtfsTime>,
+    pub stop_id: String,
+    pub stop_sequence: i32,
+    pub pickup_type: PickupDropOffType,
+    pub drop_off_type: PickupDropOffType,
 }
 
-#[derive(Queryable)]
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "stops"]
 pub struct Stop {
-    stop_id: i32,
-    stop_code: Option<i32>,
-    stop_name: String,
-    stop_desc: Option<String>,
-    stop_lat: f32,
-    stop_lon: f32,
-    zone_id: Option<i32>,
-    stop_url: Option<String>,
-    location_type: i32,
-    parent_station: Option<String>,
-    platform_code: Option<String>,
+    pub stop_id: String,
+    pub stop_code: Option<String>,
+    pub stop_name: String,
+    pub stop_desc: Option<String>,
+    pub stop_lat: f32,
+    pub stop_lon: f32,
+    pub zone_id: Option<String>,
+    pub stop_url: Option<String>,
+    pub location_type: LocationType,
+    pub parent_station: Option<String>,
+    pub platform_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "transfers"]
+pub struct Transfer {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub transfer_type: i32,
+    pub min_transfer_time: Option<i32>,
 }
 
-#[derive(Queryable)]
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "trips"]
 pub struct Trip {
-    route_id: String,
-    service_id: String,
-    trip_id: String,
-    trip_headsign: String,
-    direction_id: i32,
-    block_id: Option<String>,
-    shape_id: Option<String>,
+    pub route_id: String,
+    pub service_id: String,
+    pub trip_id: String,
+    pub trip_headsign: String,
+    pub direction_id: i32,
+    pub block_id: Option<String>,
+    pub shape_id: Option<String>,
 }
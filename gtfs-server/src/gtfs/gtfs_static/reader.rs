@@ -0,0 +1,353 @@
+//! Reader for GTFS-static CSV files.
+//!
+//! Fields are accessed by their header name rather than position, as the GTFS specification
+//! allows columns to appear in any order and for optional (or unknown) columns to be omitted or
+//! added. Quoting follows RFC 4180, and UTF-8 byte order marks and CRLF line endings are accepted.
+
+use crate::gtfs::gtfs_static::types::ParseValueError;
+use crate::gtfs::gtfs_static::GtfsStaticError;
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::num::{ParseFloatError, ParseIntError};
+use std::rc::Rc;
+use std::str::FromStr;
+
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// Position of a field within a GTFS-static file, reported alongside parsing errors.
+#[derive(Debug, Clone)]
+pub struct FileLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: String,
+}
+
+impl std::fmt::Display for FileLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:} line {:}, column \"{:}\"",
+            self.file, self.line, self.column
+        )
+    }
+}
+
+/// Conversion of a field parsing error into a ```GtfsStaticError``` at the field's location.
+pub trait IntoFieldError {
+    fn into_field_error(self, location: FileLocation) -> GtfsStaticError;
+}
+
+impl IntoFieldError for ParseIntError {
+    fn into_field_error(self, location: FileLocation) -> GtfsStaticError {
+        GtfsStaticError::ParseIntError(location, self)
+    }
+}
+
+impl IntoFieldError for ParseFloatError {
+    fn into_field_error(self, location: FileLocation) -> GtfsStaticError {
+        GtfsStaticError::ParseFloatError(location, self)
+    }
+}
+
+impl IntoFieldError for chrono::ParseError {
+    fn into_field_error(self, location: FileLocation) -> GtfsStaticError {
+        GtfsStaticError::ParseDateError(location, self)
+    }
+}
+
+impl IntoFieldError for ParseValueError {
+    fn into_field_error(self, location: FileLocation) -> GtfsStaticError {
+        GtfsStaticError::ParseValueError(location, self)
+    }
+}
+
+/// A single record of a GTFS-static file, with fields accessed by their column name.
+pub struct Record {
+    file: Rc<str>,
+    line: usize,
+    columns: Rc<HashMap<String, usize>>,
+    fields: Vec<String>,
+}
+
+impl Record {
+    /// Line number the record starts on (the header is line 1).
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Location of a column within this record, for error reporting.
+    pub fn location(&self, column: &str) -> FileLocation {
+        FileLocation {
+            file: String::from(&*self.file),
+            line: self.line,
+            column: String::from(column),
+        }
+    }
+
+    /// Get a field by column name, returning ```None``` if the column is missing or empty.
+    pub fn get(&self, column: &str) -> Option<&str> {
+        self.columns
+            .get(column)
+            .and_then(|&i| self.fields.get(i))
+            .map(|field| field.trim())
+            .filter(|field| !field.is_empty())
+    }
+
+    /// Get a field that must be present.
+    pub fn required(&self, column: &str) -> Result<&str, GtfsStaticError> {
+        self.get(column)
+            .ok_or_else(|| GtfsStaticError::ParseNoneError(self.location(column)))
+    }
+
+    /// Parse a field that must be present.
+    pub fn parse<T>(&self, column: &str) -> Result<T, GtfsStaticError>
+    where
+        T: FromStr,
+        T::Err: IntoFieldError,
+    {
+        self.required(column)?
+            .parse::<T>()
+            .map_err(|e| e.into_field_error(self.location(column)))
+    }
+
+    /// Parse a field that may be missing or empty.
+    pub fn parse_optional<T>(&self, column: &str) -> Result<Option<T>, GtfsStaticError>
+    where
+        T: FromStr,
+        T::Err: IntoFieldError,
+    {
+        match self.get(column) {
+            None => Ok(None),
+            Some(field) => match field.parse::<T>() {
+                Ok(value) => Ok(Some(value)),
+                Err(e) => Err(e.into_field_error(self.location(column))),
+            },
+        }
+    }
+
+    /// Parse a field, using ```default``` if the field is missing or empty.
+    pub fn parse_or<T>(&self, column: &str, default: T) -> Result<T, GtfsStaticError>
+    where
+        T: FromStr,
+        T::Err: IntoFieldError,
+    {
+        Ok(self.parse_optional(column)?.unwrap_or(default))
+    }
+
+    /// Parse a date field in the GTFS ```YYYYMMDD``` format that must be present.
+    pub fn parse_date(&self, column: &str) -> Result<NaiveDate, GtfsStaticError> {
+        self.parse_date_optional(column)?
+            .ok_or_else(|| GtfsStaticError::ParseNoneError(self.location(column)))
+    }
+
+    /// Parse a date field in the GTFS ```YYYYMMDD``` format that may be missing or empty.
+    pub fn parse_date_optional(&self, column: &str) -> Result<Option<NaiveDate>, GtfsStaticError> {
+        match self.get(column) {
+            None => Ok(None),
+            Some(field) => match NaiveDate::parse_from_str(field, "%Y%m%d") {
+                Ok(date) => Ok(Some(date)),
+                Err(e) => Err(e.into_field_error(self.location(column))),
+            },
+        }
+    }
+}
+
+/// Streaming reader over the records of a single GTFS-static file.
+pub struct GtfsReader<R: BufRead> {
+    file: Rc<str>,
+    reader: R,
+    line: usize,
+    columns: Rc<HashMap<String, usize>>,
+    header: Vec<String>,
+    buf: String,
+}
+
+impl<R: BufRead> GtfsReader<R> {
+    /// Create a reader for the file named ```file``` (used in error messages), reading the header
+    /// row immediately. An empty file is treated as having no columns and no records.
+    pub fn new(file: &str, reader: R) -> Result<Self, GtfsStaticError> {
+        let mut gtfs_reader = GtfsReader {
+            file: Rc::from(file),
+            reader,
+            line: 0,
+            columns: Rc::new(HashMap::new()),
+            header: Vec::new(),
+            buf: String::new(),
+        };
+
+        if let Some((_, header)) = gtfs_reader.read_fields()? {
+            let header: Vec<String> = header.into_iter().map(|h| String::from(h.trim())).collect();
+            gtfs_reader.columns = Rc::new(
+                header
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| (name.clone(), i))
+                    .collect(),
+            );
+            gtfs_reader.header = header;
+        }
+
+        Ok(gtfs_reader)
+    }
+
+    /// Read the next non-blank record, returning its starting line number and fields.
+    fn read_fields(&mut self) -> Result<Option<(usize, Vec<String>)>, GtfsStaticError> {
+        loop {
+            let start_line = self.line + 1;
+            let mut fields = Vec::new();
+            let mut field = String::new();
+            let mut in_quotes = false;
+
+            loop {
+                self.buf.clear();
+                if self.reader.read_line(&mut self.buf)? == 0 {
+                    if in_quotes {
+                        let column = self
+                            .header
+                            .get(fields.len())
+                            .cloned()
+                            .unwrap_or_else(|| format!("#{:}", fields.len() + 1));
+                        return Err(GtfsStaticError::UnterminatedQuote(FileLocation {
+                            file: String::from(&*self.file),
+                            line: start_line,
+                            column,
+                        }));
+                    }
+                    if fields.is_empty() && field.is_empty() {
+                        return Ok(None);
+                    }
+                    break;
+                }
+                self.line += 1;
+
+                let mut line = self.buf.as_str();
+                if self.line == 1 {
+                    line = line.trim_start_matches(BYTE_ORDER_MARK);
+                }
+                let line = line.trim_end_matches('\n').trim_end_matches('\r');
+
+                let mut chars = line.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if in_quotes {
+                        if c == '"' {
+                            if chars.peek() == Some(&'"') {
+                                field.push('"');
+                                chars.next();
+                            } else {
+                                in_quotes = false;
+                            }
+                        } else {
+                            field.push(c);
+                        }
+                    } else {
+                        match c {
+                            ',' => fields.push(std::mem::take(&mut field)),
+                            '"' if field.trim().is_empty() => {
+                                field.clear();
+                                in_quotes = true;
+                            }
+                            _ => field.push(c),
+                        }
+                    }
+                }
+
+                if in_quotes {
+                    // quoted fields may span lines, keeping the line break
+                    field.push('\n');
+                } else {
+                    break;
+                }
+            }
+
+            fields.push(field);
+            if fields.len() == 1 && fields[0].trim().is_empty() {
+                // skip blank lines
+                continue;
+            }
+            return Ok(Some((start_line, fields)));
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for GtfsReader<R> {
+    type Item = Result<Record, GtfsStaticError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_fields() {
+            Ok(Some((line, fields))) => Some(Ok(Record {
+                file: self.file.clone(),
+                line,
+                columns: self.columns.clone(),
+                fields,
+            })),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(contents: &str) -> Vec<Record> {
+        GtfsReader::new("test.txt", contents.as_bytes())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn columns_by_header_name() {
+        let records = records("stop_name,extra,stop_id\nRoma Street,x,600029\n");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get("stop_id"), Some("600029"));
+        assert_eq!(records[0].get("stop_name"), Some("Roma Street"));
+        assert_eq!(records[0].get("stop_code"), None);
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let records = records(
+            "stop_id,stop_name\n1,\"Adelaide St, stop 18\"\n2,\"The \"\"Gabba\"\"\"\n3,\"Two\nLines\"\n",
+        );
+        assert_eq!(records[0].get("stop_name"), Some("Adelaide St, stop 18"));
+        assert_eq!(records[1].get("stop_name"), Some("The \"Gabba\""));
+        assert_eq!(records[2].get("stop_name"), Some("Two\nLines"));
+        assert_eq!(records[2].line(), 4);
+    }
+
+    #[test]
+    fn byte_order_mark_and_crlf() {
+        let records = records("\u{feff}service_id,date\r\nWEEKDAY,20210801\r\n\r\n");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get("service_id"), Some("WEEKDAY"));
+        assert_eq!(records[0].parse::<i32>("date").unwrap(), 20210801);
+    }
+
+    #[test]
+    fn error_location() {
+        let records = records("stop_id,stop_lat\n1,-27.4\n2,north\n");
+        match records[1].parse::<f32>("stop_lat") {
+            Err(GtfsStaticError::ParseFloatError(location, _)) => {
+                assert_eq!(location.file, "test.txt");
+                assert_eq!(location.line, 3);
+                assert_eq!(location.column, "stop_lat");
+            }
+            _ => panic!("expected float parse error"),
+        }
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let mut reader = GtfsReader::new("test.txt", "a,b\n1,\"open\n".as_bytes()).unwrap();
+        match reader.next() {
+            Some(Err(GtfsStaticError::UnterminatedQuote(location))) => {
+                assert_eq!(location.line, 2);
+                assert_eq!(location.column, "b");
+            }
+            _ => panic!("expected unterminated quote error"),
+        }
+    }
+}
@@ -1,3 +1,16 @@
+table! {
+    agency (agency_id) {
+        agency_id -> Text,
+        agency_name -> Text,
+        agency_url -> Text,
+        agency_timezone -> Text,
+        agency_lang -> Nullable<Text>,
+        agency_phone -> Nullable<Text>,
+        agency_fare_url -> Nullable<Text>,
+        agency_email -> Nullable<Text>,
+    }
+}
+
 table! {
     calendar (service_id) {
         service_id -> Text,
@@ -8,24 +21,69 @@ table! {
         friday -> Int4,
         saturday -> Int4,
         sunday -> Int4,
-        start_date -> Int4,
-        end_date -> Int4,
+        start_date -> Date,
+        end_date -> Date,
     }
 }
 
 table! {
-    calendar_dates (service_id) {
+    calendar_dates (service_id, date) {
         service_id -> Text,
-        date -> Int4,
+        date -> Date,
         exception_type -> Int4,
     }
 }
 
+table! {
+    fare_attributes (fare_id) {
+        fare_id -> Text,
+        price -> Float4,
+        currency_type -> Text,
+        payment_method -> Int4,
+        transfers -> Nullable<Int4>,
+        agency_id -> Nullable<Text>,
+        transfer_duration -> Nullable<Int4>,
+    }
+}
+
+table! {
+    fare_rules (fare_id, route_id, origin_id, destination_id, contains_id) {
+        fare_id -> Text,
+        route_id -> Text,
+        origin_id -> Text,
+        destination_id -> Text,
+        contains_id -> Text,
+    }
+}
+
+table! {
+    feed_info (feed_publisher_name) {
+        feed_publisher_name -> Text,
+        feed_publisher_url -> Text,
+        feed_lang -> Text,
+        feed_start_date -> Nullable<Date>,
+        feed_end_date -> Nullable<Date>,
+        feed_version -> Nullable<Text>,
+        feed_contact_email -> Nullable<Text>,
+        feed_contact_url -> Nullable<Text>,
+    }
+}
+
+table! {
+    frequencies (trip_id, start_time) {
+        trip_id -> Text,
+        start_time -> Int4,
+        end_time -> Int4,
+        headway_secs -> Int4,
+        exact_times -> Int4,
+    }
+}
+
 table! {
     routes (route_id) {
         route_id -> Text,
-        route_short_name -> Int4,
-        route_long_name -> Text,
+        route_short_name -> Nullable<Text>,
+        route_long_name -> Nullable<Text>,
         route_desc -> Nullable<Text>,
         route_type -> Int4,
         route_url -> Text,
@@ -35,11 +93,21 @@ table! {
 }
 
 table! {
-    stop_times (trip_id) {
+    shapes (shape_id, shape_pt_sequence) {
+        shape_id -> Text,
+        shape_pt_lat -> Float4,
+        shape_pt_lon -> Float4,
+        shape_pt_sequence -> Int4,
+        shape_dist_traveled -> Nullable<Float4>,
+    }
+}
+
+table! {
+    stop_times (trip_id, stop_sequence) {
         trip_id -> Text,
-        arrival_time -> Text,
-        departure_time -> Text,
-        stop_id -> Int4,
+        arrival_time -> Nullable<Int4>,
+        departure_time -> Nullable<Int4>,
+        stop_id -> Text,
         stop_sequence -> Int4,
         pickup_type -> Int4,
         drop_off_type -> Int4,
@@ -48,13 +116,13 @@ table! {
 
 table! {
     stops (stop_id) {
-        stop_id -> Int4,
-        stop_code -> Nullable<Int4>,
+        stop_id -> Text,
+        stop_code -> Nullable<Text>,
         stop_name -> Text,
         stop_desc -> Nullable<Text>,
         stop_lat -> Float4,
         stop_lon -> Float4,
-        zone_id -> Nullable<Int4>,
+        zone_id -> Nullable<Text>,
         stop_url -> Nullable<Text>,
         location_type -> Int4,
         parent_station -> Nullable<Text>,
@@ -62,6 +130,15 @@ table! {
     }
 }
 
+table! {
+    transfers (from_stop_id, to_stop_id) {
+        from_stop_id -> Text,
+        to_stop_id -> Text,
+        transfer_type -> Int4,
+        min_transfer_time -> Nullable<Int4>,
+    }
+}
+
 table! {
     trips (trip_id) {
         route_id -> Text,
@@ -74,4 +151,18 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(calendar, calendar_dates, routes, stop_times, stops, trips,);
+allow_tables_to_appear_in_same_query!(
+    agency,
+    calendar,
+    calendar_dates,
+    fare_attributes,
+    fare_rules,
+    feed_info,
+    frequencies,
+    routes,
+    shapes,
+    stop_times,
+    stops,
+    transfers,
+    trips,
+);
@@ -0,0 +1,141 @@
+-- SQLite equivalent of the Postgres migrations, applied when generating a SQLite database.
+-- Dates are stored as ISO 8601 text and times as seconds since the start of the service day.
+
+CREATE TABLE IF NOT EXISTS agency (
+    agency_id       TEXT    NOT NULL PRIMARY KEY,
+    agency_name     TEXT    NOT NULL,
+    agency_url      TEXT    NOT NULL,
+    agency_timezone TEXT    NOT NULL,
+    agency_lang     TEXT,
+    agency_phone    TEXT,
+    agency_fare_url TEXT,
+    agency_email    TEXT
+);
+
+CREATE TABLE IF NOT EXISTS calendar (
+    service_id  TEXT    NOT NULL PRIMARY KEY,
+    monday      INTEGER NOT NULL,
+    tuesday     INTEGER NOT NULL,
+    wednesday   INTEGER NOT NULL,
+    thursday    INTEGER NOT NULL,
+    friday      INTEGER NOT NULL,
+    saturday    INTEGER NOT NULL,
+    sunday      INTEGER NOT NULL,
+    start_date  TEXT    NOT NULL,
+    end_date    TEXT    NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS calendar_dates (
+    service_id      TEXT    NOT NULL,
+    date            TEXT    NOT NULL,
+    exception_type  INTEGER NOT NULL,
+    PRIMARY KEY (service_id, date)
+);
+
+CREATE TABLE IF NOT EXISTS fare_attributes (
+    fare_id             TEXT    NOT NULL PRIMARY KEY,
+    price               REAL    NOT NULL,
+    currency_type       TEXT    NOT NULL,
+    payment_method      INTEGER NOT NULL,
+    transfers           INTEGER,
+    agency_id           TEXT,
+    transfer_duration   INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS fare_rules (
+    fare_id         TEXT    NOT NULL,
+    route_id        TEXT    NOT NULL DEFAULT '',
+    origin_id       TEXT    NOT NULL DEFAULT '',
+    destination_id  TEXT    NOT NULL DEFAULT '',
+    contains_id     TEXT    NOT NULL DEFAULT '',
+    PRIMARY KEY (fare_id, route_id, origin_id, destination_id, contains_id)
+);
+
+CREATE TABLE IF NOT EXISTS feed_info (
+    feed_publisher_name TEXT    NOT NULL PRIMARY KEY,
+    feed_publisher_url  TEXT    NOT NULL,
+    feed_lang           TEXT    NOT NULL,
+    feed_start_date     TEXT,
+    feed_end_date       TEXT,
+    feed_version        TEXT,
+    feed_contact_email  TEXT,
+    feed_contact_url    TEXT
+);
+
+CREATE TABLE IF NOT EXISTS frequencies (
+    trip_id         TEXT    NOT NULL,
+    start_time      INTEGER NOT NULL,
+    end_time        INTEGER NOT NULL,
+    headway_secs    INTEGER NOT NULL,
+    exact_times     INTEGER NOT NULL,
+    PRIMARY KEY (trip_id, start_time)
+);
+
+CREATE TABLE IF NOT EXISTS routes (
+    route_id            TEXT    NOT NULL PRIMARY KEY,
+    route_short_name    TEXT,
+    route_long_name     TEXT,
+    route_desc          TEXT,
+    route_type          INTEGER NOT NULL,
+    route_url           TEXT    NOT NULL,
+    route_color         TEXT    NOT NULL,
+    route_text_color    TEXT    NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS shapes (
+    shape_id            TEXT    NOT NULL,
+    shape_pt_lat        REAL    NOT NULL,
+    shape_pt_lon        REAL    NOT NULL,
+    shape_pt_sequence   INTEGER NOT NULL,
+    shape_dist_traveled REAL,
+    PRIMARY KEY (shape_id, shape_pt_sequence)
+);
+
+CREATE TABLE IF NOT EXISTS stop_times (
+    trip_id         TEXT    NOT NULL,
+    arrival_time    INTEGER,
+    departure_time  INTEGER,
+    stop_id         TEXT    NOT NULL,
+    stop_sequence   INTEGER NOT NULL,
+    pickup_type     INTEGER NOT NULL,
+    drop_off_type   INTEGER NOT NULL,
+    PRIMARY KEY (trip_id, stop_sequence)
+);
+
+CREATE TABLE IF NOT EXISTS stops (
+    stop_id         TEXT    NOT NULL PRIMARY KEY,
+    stop_code       TEXT,
+    stop_name       TEXT    NOT NULL,
+    stop_desc       TEXT,
+    stop_lat        REAL    NOT NULL,
+    stop_lon        REAL    NOT NULL,
+    zone_id         TEXT,
+    stop_url        TEXT,
+    location_type   INTEGER NOT NULL,
+    parent_station  TEXT,
+    platform_code   TEXT
+);
+
+CREATE TABLE IF NOT EXISTS transfers (
+    from_stop_id        TEXT    NOT NULL,
+    to_stop_id          TEXT    NOT NULL,
+    transfer_type       INTEGER NOT NULL,
+    min_transfer_time   INTEGER,
+    PRIMARY KEY (from_stop_id, to_stop_id)
+);
+
+CREATE TABLE IF NOT EXISTS trips (
+    route_id        TEXT    NOT NULL,
+    service_id      TEXT    NOT NULL,
+    trip_id         TEXT    NOT NULL PRIMARY KEY,
+    trip_headsign   TEXT    NOT NULL,
+    direction_id    INTEGER NOT NULL,
+    block_id        TEXT,
+    shape_id        TEXT
+);
+
+CREATE INDEX IF NOT EXISTS stop_times_stop_id_idx ON stop_times (stop_id);
+CREATE INDEX IF NOT EXISTS trips_route_id_idx ON trips (route_id);
+CREATE INDEX IF NOT EXISTS trips_service_id_idx ON trips (service_id);
+CREATE INDEX IF NOT EXISTS shapes_shape_id_idx ON shapes (shape_id);
+CREATE INDEX IF NOT EXISTS frequencies_trip_id_idx ON frequencies (trip_id);
@@ -0,0 +1,118 @@
+//! Small GTFS-static feed shared by tests.
+//!
+//! Services: WEEKDAY (Mon-Fri) and WEEKEND (Sat-Sun) run July to December 2021, except Wednesday
+//! 11 August 2021 (Ekka holiday) which runs the WEEKEND service instead. SPECIAL only runs on
+//! Thursday 12 August 2021 and is defined by calendar_dates.txt alone.
+//!
+//! Trips (UQ Lakes 1882 -> Cultural Centre 10795 -> Roma Street 600029, or the reverse):
+//!     - T1 BUZ WEEKDAY towards the city, 08:00 - 08:25.
+//!     - T2 BUZ WEEKDAY towards UQ Lakes, 08:30 - 08:55.
+//!     - T3 66 WEEKEND towards the city, 09:00 - 09:25.
+//!     - T4 BUZ WEEKDAY towards the city, 23:50 - 24:20 (running past midnight).
+//!     - T5 66 SPECIAL towards the city, 12:00 - 12:30 (skipping Cultural Centre).
+//!
+//! T1 and T4 follow shape BUZ-CITY, which bends west between UQ Lakes and Cultural Centre. The
+//! other trips have no shape.
+
+use crate::gtfs::gtfs_static::import::StaticFeed;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const FILES: [(&str, &str); 9] = [
+    (
+        "agency.txt",
+        "agency_name,agency_url,agency_timezone\n\
+         TransLink,https://translink.com.au,Australia/Brisbane\n",
+    ),
+    (
+        "calendar.txt",
+        "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+         WEEKDAY,1,1,1,1,1,0,0,20210701,20211231\n\
+         WEEKEND,0,0,0,0,0,1,1,20210701,20211231\n",
+    ),
+    (
+        "calendar_dates.txt",
+        "service_id,date,exception_type\n\
+         WEEKDAY,20210811,2\n\
+         WEEKEND,20210811,1\n\
+         SPECIAL,20210812,1\n",
+    ),
+    (
+        "routes.txt",
+        "route_id,route_short_name,route_long_name,route_type\n\
+         BUZ-1,BUZ,UQ Lakes - City,3\n\
+         66-1,66,UQ Lakes - RBWH Busway Station,3\n",
+    ),
+    (
+        "stops.txt",
+        "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station\n\
+         place_rms,Roma Street station,-27.4655,153.0197,1,\n\
+         600029,\"Roma Street station, platform 4\",-27.4656,153.0193,0,place_rms\n\
+         10795,Cultural Centre station,-27.4724,153.0183,0,\n\
+         1882,UQ Lakes station,-27.4977,153.0176,0,\n",
+    ),
+    (
+        "trips.txt",
+        "route_id,service_id,trip_id,trip_headsign,direction_id,shape_id\n\
+         BUZ-1,WEEKDAY,T1,City,0,BUZ-CITY\n\
+         BUZ-1,WEEKDAY,T2,UQ Lakes,1,\n\
+         66-1,WEEKEND,T3,RBWH,0,\n\
+         BUZ-1,WEEKDAY,T4,City,0,BUZ-CITY\n\
+         66-1,SPECIAL,T5,RBWH,0,\n",
+    ),
+    (
+        "shapes.txt",
+        "shape_id,shape_pt_lat,shape_pt_lon,shape_pt_sequence\n\
+         BUZ-CITY,-27.4977,153.0176,1\n\
+         BUZ-CITY,-27.4900,153.0100,2\n\
+         BUZ-CITY,-27.4780,153.0100,3\n\
+         BUZ-CITY,-27.4724,153.0183,4\n\
+         BUZ-CITY,-27.4656,153.0193,5\n",
+    ),
+    (
+        "stop_times.txt",
+        "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+         T1,08:00:00,08:00:00,1882,1\n\
+         T1,08:15:00,08:15:00,10795,2\n\
+         T1,08:25:00,08:25:00,600029,3\n\
+         T2,08:30:00,08:30:00,600029,1\n\
+         T2,08:40:00,08:40:00,10795,2\n\
+         T2,08:55:00,08:55:00,1882,3\n\
+         T3,09:00:00,09:00:00,1882,1\n\
+         T3,09:15:00,09:15:00,10795,2\n\
+         T3,09:25:00,09:25:00,600029,3\n\
+         T4,23:50:00,23:50:00,1882,1\n\
+         T4,24:05:00,24:05:00,10795,2\n\
+         T4,24:20:00,24:20:00,600029,3\n\
+         T5,12:00:00,12:00:00,1882,1\n\
+         T5,12:30:00,12:30:00,600029,2\n",
+    ),
+    (
+        "feed_info.txt",
+        "feed_publisher_name,feed_publisher_url,feed_lang,feed_end_date\n\
+         TransLink,https://translink.com.au,en,20211231\n",
+    ),
+];
+
+/// Write the test feed to a new directory, returning its path.
+pub fn write_feed() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "gtfs_server_test_feed_{:}_{:}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in FILES.iter() {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+    dir.to_string_lossy().into_owned()
+}
+
+/// Read the test feed.
+pub fn feed() -> StaticFeed {
+    let dir = write_feed();
+    let feed = StaticFeed::from_path(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    feed
+}
@@ -0,0 +1,306 @@
+//! Field types defined by the GTFS-static specification, stored in the database as integers.
+
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Integer;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Error parsing a GTFS-static field which is not a valid value for its type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseValueError {
+    value: String,
+    expected: &'static str,
+}
+
+impl ParseValueError {
+    fn new(value: &str, expected: &'static str) -> Self {
+        ParseValueError {
+            value: String::from(value),
+            expected,
+        }
+    }
+}
+
+impl std::error::Error for ParseValueError {}
+
+impl std::fmt::Display for ParseValueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{:}\" is not a valid {:}", self.value, self.expected)
+    }
+}
+
+/// Implement conversion to and from an ```Integer``` column for a type with ```from_i32``` and
+/// ```From<Type> for i32```.
+macro_rules! integer_sql_conversions {
+    ($name:ident, $description:expr) => {
+        impl<DB: Backend> ToSql<Integer, DB> for $name
+        where
+            i32: ToSql<Integer, DB>,
+        {
+            fn to_sql<W: Write>(&self, out: &mut Output<W, DB>) -> serialize::Result {
+                i32::from(*self).to_sql(out)
+            }
+        }
+
+        impl<DB: Backend> FromSql<Integer, DB> for $name
+        where
+            i32: FromSql<Integer, DB>,
+        {
+            fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+                let value = i32::from_sql(bytes)?;
+                $name::from_i32(value)
+                    .ok_or_else(|| format!("{:} is not a valid {:}", value, $description).into())
+            }
+        }
+    };
+}
+
+/// Define an enumeration of the integer values permitted for a GTFS-static field.
+macro_rules! gtfs_enum {
+    (
+        $(#[$meta:meta])*
+        $name:ident, $description:expr,
+        { $($(#[$variant_meta:meta])* $variant:ident = $value:expr,)+ }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AsExpression, FromSqlRow)]
+        #[sql_type = "Integer"]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)+
+        }
+
+        impl $name {
+            pub fn from_i32(value: i32) -> Option<Self> {
+                match value {
+                    $($value => Some($name::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(value: $name) -> i32 {
+                match value {
+                    $($name::$variant => $value,)+
+                }
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseValueError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse::<i32>()
+                    .ok()
+                    .and_then($name::from_i32)
+                    .ok_or_else(|| ParseValueError::new(s, $description))
+            }
+        }
+
+        integer_sql_conversions!($name, $description);
+    };
+}
+
+gtfs_enum! {
+    /// Type of location described by a stop (stops.txt location_type).
+    LocationType, "location type", {
+        Stop = 0,
+        Station = 1,
+        EntranceExit = 2,
+        GenericNode = 3,
+        BoardingArea = 4,
+    }
+}
+
+gtfs_enum! {
+    /// Whether passengers may board or alight at a stop (stop_times.txt pickup_type and
+    /// drop_off_type).
+    PickupDropOffType, "pickup/drop off type", {
+        Regular = 0,
+        NoneAvailable = 1,
+        PhoneAgency = 2,
+        CoordinateWithDriver = 3,
+    }
+}
+
+gtfs_enum! {
+    /// Whether a calendar date adds or removes service (calendar_dates.txt exception_type).
+    ExceptionType, "exception type", {
+        Added = 1,
+        Removed = 2,
+    }
+}
+
+/// Type of transportation used on a route (routes.txt route_type).
+///
+/// Feeds may also use the extended route types (e.g. 700 for bus service), which are kept as
+/// ```Other```.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AsExpression, FromSqlRow)]
+#[sql_type = "Integer"]
+pub enum RouteType {
+    Tram,
+    Subway,
+    Rail,
+    Bus,
+    Ferry,
+    CableTram,
+    AerialLift,
+    Funicular,
+    Trolleybus,
+    Monorail,
+    Other(i32),
+}
+
+impl RouteType {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        use RouteType::*;
+        Some(match value {
+            0 => Tram,
+            1 => Subway,
+            2 => Rail,
+            3 => Bus,
+            4 => Ferry,
+            5 => CableTram,
+            6 => AerialLift,
+            7 => Funicular,
+            11 => Trolleybus,
+            12 => Monorail,
+            other => Other(other),
+        })
+    }
+}
+
+impl From<RouteType> for i32 {
+    fn from(value: RouteType) -> i32 {
+        use RouteType::*;
+        match value {
+            Tram => 0,
+            Subway => 1,
+            Rail => 2,
+            Bus => 3,
+            Ferry => 4,
+            CableTram => 5,
+            AerialLift => 6,
+            Funicular => 7,
+            Trolleybus => 11,
+            Monorail => 12,
+            Other(other) => other,
+        }
+    }
+}
+
+impl FromStr for RouteType {
+    type Err = ParseValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<i32>()
+            .ok()
+            .filter(|&value| value >= 0)
+            .and_then(RouteType::from_i32)
+            .ok_or_else(|| ParseValueError::new(s, "route type"))
+    }
+}
+
+integer_sql_conversions!(RouteType, "route type");
+
+/// Time within a service day, as the number of seconds since "noon minus 12h" of the service day
+/// (midnight, except on days with daylight saving changes).
+///
+/// Trips running past midnight keep the date of the service day they started on, so times may be
+/// later than 24:00:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, AsExpression, FromSqlRow)]
+#[sql_type = "Integer"]
+pub struct GtfsTime(i32);
+
+impl GtfsTime {
+    pub fn from_seconds(seconds: i32) -> Self {
+        GtfsTime(seconds)
+    }
+
+    pub fn from_hms(hours: i32, minutes: i32, seconds: i32) -> Self {
+        GtfsTime(hours * 3600 + minutes * 60 + seconds)
+    }
+
+    fn from_i32(seconds: i32) -> Option<Self> {
+        Some(GtfsTime(seconds))
+    }
+
+    /// Seconds since "noon minus 12h" of the service day.
+    pub fn seconds(self) -> i32 {
+        self.0
+    }
+}
+
+impl From<GtfsTime> for i32 {
+    fn from(time: GtfsTime) -> i32 {
+        time.0
+    }
+}
+
+impl FromStr for GtfsTime {
+    type Err = ParseValueError;
+
+    /// Parse a time in the GTFS ```H:MM:SS``` or ```HH:MM:SS``` format.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseValueError::new(s, "time");
+
+        let mut parts = s.split(':');
+        let mut next_part = |max: i32| {
+            parts
+                .next()
+                .and_then(|part| part.parse::<i32>().ok())
+                .filter(|&value| value >= 0 && value < max)
+                .ok_or_else(error)
+        };
+        let hours = next_part(i32::MAX / 3600)?;
+        let minutes = next_part(60)?;
+        let seconds = next_part(60)?;
+
+        if parts.next().is_some() {
+            return Err(error());
+        }
+        Ok(GtfsTime::from_hms(hours, minutes, seconds))
+    }
+}
+
+impl std::fmt::Display for GtfsTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            self.0 / 3600,
+            self.0 / 60 % 60,
+            self.0 % 60
+        )
+    }
+}
+
+integer_sql_conversions!(GtfsTime, "time");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_time() {
+        assert_eq!("8:05:30".parse(), Ok(GtfsTime::from_seconds(29130)));
+        assert_eq!("25:10:00".parse(), Ok(GtfsTime::from_hms(25, 10, 0)));
+        assert_eq!(GtfsTime::from_hms(25, 10, 0).to_string(), "25:10:00");
+        assert!("08:60:00".parse::<GtfsTime>().is_err());
+        assert!("08:00".parse::<GtfsTime>().is_err());
+        assert!("08:00:00:00".parse::<GtfsTime>().is_err());
+    }
+
+    #[test]
+    fn parse_enums() {
+        assert_eq!("3".parse(), Ok(RouteType::Bus));
+        assert_eq!("700".parse(), Ok(RouteType::Other(700)));
+        assert_eq!(i32::from(RouteType::Other(700)), 700);
+        assert_eq!("2".parse(), Ok(ExceptionType::Removed));
+        assert!("0".parse::<ExceptionType>().is_err());
+        assert_eq!("1".parse(), Ok(LocationType::Station));
+        assert_eq!("3".parse(), Ok(PickupDropOffType::CoordinateWithDriver));
+    }
+}
@@ -4,6 +4,7 @@
 extern crate diesel;
 extern crate dotenv;
 
+pub mod geo;
 pub mod gtfs;
 pub mod requests;
 pub mod server;
@@ -7,7 +7,8 @@
 //! scheduled arrival time, expected arrival time, vehicle type (e.g. NGR, IMU etc. for trains),
 //! with certain mandatory fields such as a route identifier.
 //!
-//! The communication protocol is defined within the gtfs-requests.proto file.
+//! The communication protocol is defined within the gtfs-requests.proto file, with the compiled
+//! messages in ```gtfs_server::requests::protocol```.
 //!
 //! Future work:
 //!     - Extend request/response types to more than departure board information.
@@ -17,11 +18,18 @@
 //!       the embedded device to compute closest vehicle/vehicle most likely to be closest.
 //!     - Extend the program to accept a real-time feed url and static database path to allow the
 //!       server to process non-seq requests, which will be the default feed for development.
-//!     - Add protobuf compilation to build.rs
 
 use gtfs_server::gtfs::gtfs_real_time as rt;
-use gtfs_server::gtfs::gtfs_real_time::FeedType;
+use gtfs_server::gtfs::gtfs_real_time::archive::{Recorder, Replay};
+use gtfs_server::gtfs::gtfs_real_time::{poller, FeedType};
+use gtfs_server::gtfs::gtfs_static;
+use gtfs_server::gtfs::gtfs_static::database::PostgresStatic;
+use gtfs_server::gtfs::gtfs_static::import::FeedValidity;
+use gtfs_server::server::{estimate_departures, http, serial, Server};
 use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::Duration;
 
 const _GTFS_RT_URL: &str = "https://gtfsrt.api.translink.com.au/api/realtime/SEQ";
 const GTFS_RT_TRIP_UPDATE_URL: &str =
@@ -29,43 +37,69 @@ const GTFS_RT_TRIP_UPDATE_URL: &str =
 const GTFS_RT_VEHICLE_POSITIONS_URL: &str =
     "https://gtfsrt.api.translink.com.au/api/realtime/SEQ/VehiclePositions";
 const GTFS_RT_ALERTS_URL: &str = "https://gtfsrt.api.translink.com.au/api/realtime/SEQ/Alerts";
+const GTFS_STATIC_PATH: &str = "SEQ_GTFS.zip";
+const HTTP_PORT: u16 = 8080;
+const SERIAL_BAUD_RATE: u32 = 115_200;
+const TRIP_UPDATES_REFRESH: Duration = Duration::from_secs(30);
+const VEHICLE_POSITIONS_REFRESH: Duration = Duration::from_secs(15);
+const ALERTS_REFRESH: Duration = Duration::from_secs(120);
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
-    println!("Hello, world!");
+    // Establish connection to static database, refusing to serve an expired timetable.
+    dotenv::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL")?;
+    gtfs_static::validate_static_database(GTFS_STATIC_PATH, &database_url)?;
+    let timezone = FeedValidity::from_path(GTFS_STATIC_PATH)?
+        .timezone
+        .ok_or("GTFS-static feed has no agency timezone")?;
 
-    // static server (and realtime setup if necessary -> does anything need to be checked on rt)
-
-    // let resp = rt::from_url(GTFS_RT_URL).await?;
-
-    let mut rt = rt::GtfsRt::new(
-        GTFS_RT_TRIP_UPDATE_URL,
-        GTFS_RT_VEHICLE_POSITIONS_URL,
-        GTFS_RT_ALERTS_URL,
+    // Refresh the real-time snapshot shared by every client in the background, from the live
+    // feeds or from the archive at GTFS_RT_REPLAY (at GTFS_RT_REPLAY_SPEED times real speed),
+    // recording every feed to the archive at GTFS_RT_RECORD if set.
+    let mut rt = match std::env::var("GTFS_RT_REPLAY") {
+        Ok(archive) => {
+            let speed = match std::env::var("GTFS_RT_REPLAY_SPEED") {
+                Ok(speed) => speed.parse()?,
+                Err(_) => 1.0,
+            };
+            println!("Replaying real-time feeds from {:}", archive);
+            rt::GtfsRt::from_replay(Replay::new(archive, speed)?)
+        }
+        Err(_) => rt::GtfsRt::new(
+            GTFS_RT_TRIP_UPDATE_URL,
+            GTFS_RT_VEHICLE_POSITIONS_URL,
+            GTFS_RT_ALERTS_URL,
+        ),
+    };
+    if let Ok(archive) = std::env::var("GTFS_RT_RECORD") {
+        rt.set_recorder(Recorder::new(archive)?);
+    }
+    let realtime = poller::spawn(
+        rt,
+        &[
+            (FeedType::TripUpdate, TRIP_UPDATES_REFRESH),
+            (FeedType::VehiclePosition, VEHICLE_POSITIONS_REFRESH),
+            (FeedType::Alert, ALERTS_REFRESH),
+        ],
     );
+    let server = Arc::new(Server::new(
+        Box::new(PostgresStatic::new(&database_url)?),
+        timezone,
+        realtime,
+    ));
+    tokio::spawn(estimate_departures(server.clone()));
 
-    // Establish connection to real-time feed to verify validity/expiry of gtfs static server.
-    // Establish connection to static database.
-
-    // Setup thread-pool/async based http server, ready to accept incoming connections
-
-    loop {
-        // Establish a connection to incoming requests, reading provided requests and responding with
-        // proto files containing processed requests.
-
-        let latest_fm = rt.latest(FeedType::VehiclePosition).await?;
-        // println!("Latest header\n{:?}", latest_fm.header);
-        println!("{:?}", latest_fm);
-
-        let closest = match gtfs_server::requests::closest_vehicle::find_closest(
-            &latest_fm.entity,
-            -27.52423,
-            152.81618,
-        ) {
-            Ok(entity) => entity,
-            _ => continue,
-        };
-
-        println!("Closest\n{:?}", closest);
+    // Serve devices without a network connection on the (comma separated) GTFS_SERIAL_PORTS.
+    if let Ok(ports) = std::env::var("GTFS_SERIAL_PORTS") {
+        let ports: Vec<String> = ports.split(',').map(|p| String::from(p.trim())).collect();
+        serial::listen(server.clone(), &ports, SERIAL_BAUD_RATE)?;
+        println!("Serving serial requests on {:}", ports.join(", "));
     }
+
+    // Accept incoming connections.
+    let address = SocketAddr::from(([0, 0, 0, 0], HTTP_PORT));
+    println!("Serving HTTP requests on {:}", address);
+    http::serve(server, address).await?;
+    Ok(())
 }
@@ -1,91 +1,280 @@
 //! Request to find the closest service to a set of coordinates from a collection of FeedEntities.
+//!
+//! Vehicles are those entities of a vehicle positions feed with a position. Either the nearest
+//! vehicles or those within a radius are found (```VehicleQuery```), optionally only those
+//! matching a ```VehicleFilter```, in order of distance.
 
+use crate::geo::{self, BoundingBox, Point};
 use crate::gtfs::gtfs_real_time::FeedEntity;
+use crate::gtfs::gtfs_static::types::RouteType;
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use std::collections::HashMap;
 
-pub fn find_closest(entities: &Vec<FeedEntity>, lat: f32, lon: f32) -> Result<&FeedEntity, ()> {
-    let mut closest_entity = None;
-    let mut distance: Option<f32> = None;
-
-    for entity in entities {
-        // get entity coordinates, or skip if no coordinates
-        let position = match entity.vehicle.as_ref().and_then(|x| x.position.as_ref()) {
-            Some(x) => x,
-            _ => continue,
-        };
-
-        let entity_lat = position.latitude;
-        let entity_lon = position.longitude;
+/// Closest vehicle associated errors.
+#[derive(Debug)]
+pub enum ClosestVehicleError {
+    NoVehicles,
+    StaticError(GtfsStaticError),
+}
 
-        // if entity is closer, then update var
-        let entity_dist =
-            get_distance_kms_between_gps_coordinates_haversine(entity_lat, entity_lon, lat, lon);
+impl std::error::Error for ClosestVehicleError {}
 
-        match distance {
-            None => distance = Some(entity_dist),
-            Some(d) => {
-                if entity_dist < d {
-                    distance = Some(entity_dist);
-                    closest_entity = Some(entity);
-                }
-            }
+impl std::fmt::Display for ClosestVehicleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ClosestVehicleError::*;
+        match self {
+            NoVehicles => write!(f, "No vehicle positions match the query"),
+            StaticError(e) => write!(f, "Error querying static feed\n{:}", e),
         }
     }
+}
 
-    match closest_entity {
-        Some(entity) => Ok(entity),
-        None => Err(()),
+impl From<GtfsStaticError> for ClosestVehicleError {
+    fn from(e: GtfsStaticError) -> Self {
+        ClosestVehicleError::StaticError(e)
     }
 }
 
-/// return the naive distance in kilometres for a latitude/longitude difference.
-/// naive distance implementation with scaling factor for conversion.
-fn get_distance_kms_between_gps_coordinates_naive(
-    lat_1: f32,
-    lon_1: f32,
-    lat_2: f32,
-    lon_2: f32,
-) -> f32 {
-    // todo: figure out constant coefficient what it should be
-
-    let d_lat = lat_2 - lat_1;
-    let d_lon = lon_2 - lon_1;
-    (d_lat.powi(2) + d_lon.powi(2)).sqrt() * 98.459_288_21
+/// Which vehicles near a position to find.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VehicleQuery {
+    /// The given number of vehicles nearest to the position.
+    Nearest(usize),
+    /// Every vehicle within the given distance of the position, in kilometres.
+    WithinRadius(f64),
 }
 
-/// return the haversine distance in kilometres for a latitude/longitude difference
-fn get_distance_kms_between_gps_coordinates_haversine(
-    lat_1: f32,
-    lon_1: f32,
-    lat_2: f32,
-    lon_2: f32,
-) -> f32 {
-    const EARTH_RADIUS_KM: f32 = 6371.0;
+/// Vehicles to consider, every vehicle matching all of the set fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VehicleFilter {
+    pub route_id: Option<String>,
+    /// Type of the route, from the static feed.
+    pub route_type: Option<RouteType>,
+    /// User visible label of the vehicle (e.g. a fleet number).
+    pub label: Option<String>,
+}
 
-    let lat_1_rad = lat_1 * std::f32::consts::PI / 180.0;
-    let lat_2_rad = lat_2 * std::f32::consts::PI / 180.0;
+/// A vehicle found near a position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearbyVehicle<'a> {
+    pub entity: &'a FeedEntity,
+    /// Distance from the position, in kilometres.
+    pub distance_km: f64,
+}
 
-    let d_lat = (lat_2 - lat_1) * std::f32::consts::PI / 180.0;
-    let d_lon = (lon_2 - lon_1) * std::f32::consts::PI / 180.0;
+/// Find the vehicle closest to ```lat```, ```lon```.
+pub fn find_closest(
+    entities: &[FeedEntity],
+    lat: f64,
+    lon: f64,
+) -> Result<&FeedEntity, ClosestVehicleError> {
+    let mut closest = nearby(entities, lat, lon, VehicleQuery::Nearest(1), |_| Ok(true))?;
+    closest
+        .pop()
+        .map(|vehicle| vehicle.entity)
+        .ok_or(ClosestVehicleError::NoVehicles)
+}
 
-    let a = (d_lat / 2.0).sin() * (d_lat / 2.0).sin()
-        + (d_lon / 2.0).sin() * (d_lon / 2.0).sin() * lat_1_rad.cos() * lat_2_rad.cos();
+/// Find the vehicles of ```query``` near ```lat```, ```lon``` which match ```filter```, nearest
+/// first. The static feed is only queried to filter by route type, and for the routes of
+/// vehicles whose trip lacks a route_id.
+pub fn find_nearby<'a, S: GtfsStatic + ?Sized>(
+    gtfs: &S,
+    entities: &'a [FeedEntity],
+    lat: f64,
+    lon: f64,
+    query: VehicleQuery,
+    filter: &VehicleFilter,
+) -> Result<Vec<NearbyVehicle<'a>>, ClosestVehicleError> {
+    let mut route_types: HashMap<String, Option<RouteType>> = HashMap::new();
+    nearby(entities, lat, lon, query, |entity| {
+        let vehicle = match &entity.vehicle {
+            Some(vehicle) => vehicle,
+            None => return Ok(false),
+        };
+        if let Some(label) = &filter.label {
+            let vehicle_label = vehicle.vehicle.as_ref().and_then(|v| v.label.as_ref());
+            if vehicle_label != Some(label) {
+                return Ok(false);
+            }
+        }
+        if filter.route_id.is_none() && filter.route_type.is_none() {
+            return Ok(true);
+        }
 
-    let sqrt_a = a.sqrt();
-    let sqrt_1_minus_a = (1.0 - a).sqrt(); // test todo
-    let c = 2.0 * sqrt_a.atan2(sqrt_1_minus_a);
+        let trip = vehicle.trip.as_ref();
+        let route_id = match trip.and_then(|trip| trip.route_id.clone()) {
+            Some(route_id) => Some(route_id),
+            None => match trip.and_then(|trip| trip.trip_id.as_deref()) {
+                Some(trip_id) => gtfs.trip(trip_id)?.map(|trip| trip.route_id),
+                None => None,
+            },
+        };
+        let route_id = match route_id {
+            Some(route_id) => route_id,
+            None => return Ok(false),
+        };
+        if filter.route_id.is_some() && filter.route_id.as_ref() != Some(&route_id) {
+            return Ok(false);
+        }
+        if let Some(route_type) = filter.route_type {
+            if !route_types.contains_key(&route_id) {
+                let route = gtfs.route(&route_id)?;
+                route_types.insert(route_id.clone(), route.map(|route| route.route_type));
+            }
+            if route_types[&route_id] != Some(route_type) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })
+}
 
-    EARTH_RADIUS_KM * c
+/// Vehicles of ```query``` near ```lat```, ```lon``` for which ```keep``` is true, nearest
+/// first.
+fn nearby<F>(
+    entities: &[FeedEntity],
+    lat: f64,
+    lon: f64,
+    query: VehicleQuery,
+    mut keep: F,
+) -> Result<Vec<NearbyVehicle<'_>>, ClosestVehicleError>
+where
+    F: FnMut(&FeedEntity) -> Result<bool, ClosestVehicleError>,
+{
+    let point = Point::new(lat, lon);
+    let bounds = match query {
+        VehicleQuery::WithinRadius(radius) => Some(BoundingBox::around(point, radius)),
+        VehicleQuery::Nearest(_) => None,
+    };
+    let mut vehicles = Vec::new();
+    for entity in entities {
+        // get entity coordinates, or skip if no coordinates
+        let position = match entity.vehicle.as_ref().and_then(|x| x.position.as_ref()) {
+            Some(x) => Point::from_f32(x.latitude, x.longitude),
+            _ => continue,
+        };
+        if matches!(bounds, Some(bounds) if !bounds.contains(position)) {
+            continue;
+        }
+
+        let distance_km = geo::distance_km(position, point);
+        if let VehicleQuery::WithinRadius(radius) = query {
+            if distance_km > radius {
+                continue;
+            }
+        }
+        if keep(entity)? {
+            vehicles.push(NearbyVehicle {
+                entity,
+                distance_km,
+            });
+        }
+    }
+
+    vehicles.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    if let VehicleQuery::Nearest(count) = query {
+        vehicles.truncate(count);
+    }
+    Ok(vehicles)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gtfs::gtfs_real_time::{
+        Position, TripDescriptor, VehicleDescriptor, VehiclePosition,
+    };
+    use crate::gtfs::gtfs_static::memory::MemoryStatic;
+    use crate::gtfs::gtfs_static::test_feed;
+
+    fn vehicle(label: &str, trip: TripDescriptor, latitude: f32, longitude: f32) -> FeedEntity {
+        FeedEntity {
+            id: String::from(label),
+            vehicle: Some(VehiclePosition {
+                vehicle: Some(VehicleDescriptor {
+                    label: Some(String::from(label)),
+                    ..Default::default()
+                }),
+                trip: Some(trip),
+                position: Some(Position {
+                    latitude,
+                    longitude,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn ids<'a>(vehicles: &[NearbyVehicle<'a>]) -> Vec<&'a str> {
+        vehicles.iter().map(|v| v.entity.id.as_str()).collect()
+    }
 
     #[test]
-    fn distance_kms_1() {
+    fn nearest_and_within_radius() {
+        let trip = |trip_id: &str| TripDescriptor {
+            trip_id: Some(String::from(trip_id)),
+            ..Default::default()
+        };
+        let route = |route_id: &str| TripDescriptor {
+            route_id: Some(String::from(route_id)),
+            ..Default::default()
+        };
+        // UQ Lakes, Cultural Centre, Roma Street and Sydney
+        let entities = vec![
+            vehicle("1001", trip("T1"), -27.4977, 153.0176),
+            vehicle("1002", route("66-1"), -27.4724, 153.0183),
+            vehicle("1003", trip("T3"), -27.4656, 153.0193),
+            vehicle("1004", route("BUZ-1"), -33.87, 151.21),
+            FeedEntity::default(),
+        ];
+        let gtfs = MemoryStatic::from_feed(test_feed::feed());
+        let everything = VehicleFilter::default();
+
+        // the first vehicle is the closest
         assert_eq!(
-            get_distance_kms_between_gps_coordinates_naive(3.0, 4.0, 2.0, 1.0),
-            5.0
-        )
+            find_closest(&entities, -27.497, 153.017).unwrap().id,
+            "1001"
+        );
+        assert!(matches!(
+            find_closest(&entities[4..], -27.497, 153.017),
+            Err(ClosestVehicleError::NoVehicles)
+        ));
+
+        let nearest = VehicleQuery::Nearest(2);
+        let vehicles = find_nearby(&gtfs, &entities, -27.466, 153.019, nearest, &everything);
+        let vehicles = vehicles.unwrap();
+        assert_eq!(ids(&vehicles), vec!["1003", "1002"]);
+        assert!(vehicles[0].distance_km < 0.1);
+        assert!(vehicles[1].distance_km > 0.5 && vehicles[1].distance_km < 1.0);
+
+        let radius = VehicleQuery::WithinRadius(5.0);
+        let vehicles = find_nearby(&gtfs, &entities, -27.466, 153.019, radius, &everything);
+        assert_eq!(ids(&vehicles.unwrap()), vec!["1003", "1002", "1001"]);
+
+        // the route of 1001 and 1003 is found from their trips
+        let filter = VehicleFilter {
+            route_id: Some(String::from("66-1")),
+            ..Default::default()
+        };
+        let vehicles = find_nearby(&gtfs, &entities, -27.466, 153.019, radius, &filter);
+        assert_eq!(ids(&vehicles.unwrap()), vec!["1003", "1002"]);
+
+        let filter = VehicleFilter {
+            route_type: Some(RouteType::Bus),
+            label: Some(String::from("1004")),
+            ..Default::default()
+        };
+        let vehicles = find_nearby(&gtfs, &entities, -27.466, 153.019, nearest, &filter);
+        assert_eq!(ids(&vehicles.unwrap()), vec!["1004"]);
+
+        let filter = VehicleFilter {
+            route_type: Some(RouteType::Rail),
+            ..Default::default()
+        };
+        let vehicles = find_nearby(&gtfs, &entities, -27.466, 153.019, nearest, &filter);
+        assert!(vehicles.unwrap().is_empty());
     }
 }
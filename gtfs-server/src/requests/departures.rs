@@ -0,0 +1,388 @@
+//! Request for the upcoming departures from a set of stops, combining the static timetable with
+//! GTFS-RT trip updates.
+//!
+//! Trip updates are matched to scheduled departures by trip_id and start_date. A stop time update
+//! for the departure stop gives its expected time directly, otherwise the delay of the last
+//! updated stop before it (or the delay of the whole trip) carries forward. If no prediction is
+//! available, the scheduled time is returned instead, unless it can be estimated from the
+//! vehicle's position (see ```estimator```).
+
+use crate::gtfs::gtfs_real_time::trip_descriptor::ScheduleRelationship as TripRelationship;
+use crate::gtfs::gtfs_real_time::trip_update::stop_time_update::ScheduleRelationship as StopRelationship;
+use crate::gtfs::gtfs_real_time::trip_update::{StopTimeEvent, StopTimeUpdate};
+use crate::gtfs::gtfs_real_time::{FeedMessage, TripUpdate};
+use crate::gtfs::gtfs_static::calendar::service_day_start;
+use crate::gtfs::gtfs_static::departures::ScheduledDeparture;
+use crate::gtfs::gtfs_static::models::StopTime;
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+/// Source of the expected time of a departure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    /// Predicted from a GTFS-RT trip update.
+    Realtime,
+    /// Estimated locally from the vehicle's position, see ```estimator::ArrivalEstimator```.
+    Estimated,
+    /// No prediction available, the scheduled time is used.
+    Scheduled,
+}
+
+/// Whether a departure will take place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepartureStatus {
+    Running,
+    /// The whole trip has been cancelled.
+    Canceled,
+    /// The trip runs, but will not stop at the departure stop.
+    Skipped,
+}
+
+/// Scheduled departure with its expected time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Departure {
+    pub scheduled: ScheduledDeparture,
+    pub expected: DateTime<Tz>,
+    pub time_source: TimeSource,
+    pub status: DepartureStatus,
+}
+
+/// Apply the trip updates of ```trip_updates``` to the ```scheduled``` departures, returning them
+/// ordered by expected time.
+///
+/// The stop times of a trip are only queried from ```gtfs``` when an update refers to a stop by
+/// stop_id alone, or gives an absolute time for a stop before the departure stop.
+pub fn predict_departures<S: GtfsStatic + ?Sized>(
+    gtfs: &S,
+    scheduled: Vec<ScheduledDeparture>,
+    trip_updates: &FeedMessage,
+) -> Result<Vec<Departure>, GtfsStaticError> {
+    let mut updates: HashMap<&str, Vec<&TripUpdate>> = HashMap::new();
+    for trip_update in trip_updates
+        .entity
+        .iter()
+        .filter(|entity| !entity.is_deleted())
+        .filter_map(|entity| entity.trip_update.as_ref())
+    {
+        if let Some(trip_id) = &trip_update.trip.trip_id {
+            updates.entry(trip_id).or_default().push(trip_update);
+        }
+    }
+
+    let mut stop_times = TripStopTimes::default();
+    let mut departures = Vec::with_capacity(scheduled.len());
+    for departure in scheduled {
+        let trip_update = updates.get(departure.trip_id.as_str()).and_then(|updates| {
+            updates
+                .iter()
+                .find(|update| runs_on(update, departure.service_date))
+                .copied()
+        });
+        departures.push(match trip_update {
+            Some(trip_update) => predict(gtfs, departure, trip_update, &mut stop_times)?,
+            None => Departure {
+                expected: departure.departure,
+                scheduled: departure,
+                time_source: TimeSource::Scheduled,
+                status: DepartureStatus::Running,
+            },
+        });
+    }
+
+    departures.sort_by_key(|departure| departure.expected);
+    Ok(departures)
+}
+
+/// Whether ```trip_update``` describes the trip running on ```service_date```. Updates without a
+/// start date apply to any day.
+fn runs_on(trip_update: &TripUpdate, service_date: NaiveDate) -> bool {
+    match &trip_update.trip.start_date {
+        Some(start_date) => {
+            NaiveDate::parse_from_str(start_date, "%Y%m%d").ok() == Some(service_date)
+        }
+        None => true,
+    }
+}
+
+/// Expected time of ```departure``` according to its trip update.
+fn predict<S: GtfsStatic + ?Sized>(
+    gtfs: &S,
+    departure: ScheduledDeparture,
+    trip_update: &TripUpdate,
+    stop_times: &mut TripStopTimes,
+) -> Result<Departure, GtfsStaticError> {
+    let tz = departure.departure.timezone();
+    let day_start = service_day_start(departure.service_date, &tz);
+
+    let mut status = DepartureStatus::Running;
+    if trip_update.trip.schedule_relationship() == TripRelationship::Canceled {
+        status = DepartureStatus::Canceled;
+    }
+
+    // updates for the departure stop and the stops before it, in stop sequence order
+    let mut stop_time_updates: Vec<(i32, &StopTimeUpdate)> = Vec::new();
+    for stop_time_update in trip_update.stop_time_update.iter() {
+        let sequence = match (stop_time_update.stop_sequence, &stop_time_update.stop_id) {
+            (Some(sequence), _) => Some(sequence as i32),
+            (None, Some(stop_id)) => stop_times
+                .get(gtfs, &departure.trip_id)?
+                .iter()
+                .find(|stop_time| &stop_time.stop_id == stop_id)
+                .map(|stop_time| stop_time.stop_sequence),
+            (None, None) => None,
+        };
+        match sequence {
+            Some(sequence) if sequence <= departure.stop_sequence => {
+                stop_time_updates.push((sequence, stop_time_update))
+            }
+            _ => (),
+        }
+    }
+    stop_time_updates.sort_by_key(|(sequence, _)| *sequence);
+
+    let mut delay = trip_update.delay.map(i64::from);
+    let mut expected = None;
+    for (sequence, stop_time_update) in stop_time_updates {
+        let at_departure_stop = sequence == departure.stop_sequence;
+        match stop_time_update.schedule_relationship() {
+            StopRelationship::Skipped => {
+                if at_departure_stop {
+                    status = DepartureStatus::Skipped;
+                }
+            }
+            StopRelationship::NoData => delay = None,
+            StopRelationship::Scheduled | StopRelationship::Unscheduled => {
+                let event = match event(stop_time_update) {
+                    Some(event) => event,
+                    None => continue,
+                };
+                if at_departure_stop {
+                    if let Some(time) = event.time {
+                        expected = tz.timestamp_opt(time, 0).single();
+                        continue;
+                    }
+                }
+                let event_delay = match (event.delay, event.time) {
+                    (Some(event_delay), _) => Some(i64::from(event_delay)),
+                    // absolute time at an earlier stop, relative to its scheduled time
+                    (None, Some(time)) => stop_times
+                        .get(gtfs, &departure.trip_id)?
+                        .iter()
+                        .find(|stop_time| stop_time.stop_sequence == sequence)
+                        .and_then(|stop_time| stop_time.departure_time.or(stop_time.arrival_time))
+                        .map(|scheduled| {
+                            time - day_start.timestamp() - i64::from(scheduled.seconds())
+                        }),
+                    (None, None) => None,
+                };
+                if event_delay.is_some() {
+                    delay = event_delay;
+                }
+            }
+        }
+    }
+
+    let (expected, time_source) = match (expected, delay) {
+        (Some(expected), _) => (expected, TimeSource::Realtime),
+        (None, Some(delay)) => (
+            departure.departure + Duration::seconds(delay),
+            TimeSource::Realtime,
+        ),
+        (None, None) => (departure.departure, TimeSource::Scheduled),
+    };
+    Ok(Departure {
+        scheduled: departure,
+        expected,
+        time_source,
+        status,
+    })
+}
+
+/// Departure prediction of a stop time update, or the arrival prediction if the departure is not
+/// given.
+fn event(stop_time_update: &StopTimeUpdate) -> Option<&StopTimeEvent> {
+    stop_time_update
+        .departure
+        .as_ref()
+        .filter(|event| event.time.is_some() || event.delay.is_some())
+        .or(stop_time_update.arrival.as_ref())
+}
+
+/// Stop times of trips, queried when first needed.
+#[derive(Default)]
+struct TripStopTimes {
+    trips: HashMap<String, Vec<StopTime>>,
+}
+
+impl TripStopTimes {
+    fn get<S: GtfsStatic + ?Sized>(
+        &mut self,
+        gtfs: &S,
+        trip_id: &str,
+    ) -> Result<&[StopTime], GtfsStaticError> {
+        if !self.trips.contains_key(trip_id) {
+            let stop_times = gtfs.stop_times_for_trip(trip_id)?;
+            self.trips.insert(String::from(trip_id), stop_times);
+        }
+        Ok(&self.trips[trip_id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::trip_update::StopTimeUpdate;
+    use crate::gtfs::gtfs_real_time::{FeedEntity, TripDescriptor};
+    use crate::gtfs::gtfs_static::departures::scheduled_departures;
+    use crate::gtfs::gtfs_static::memory::MemoryStatic;
+    use crate::gtfs::gtfs_static::test_feed;
+    use chrono_tz::Australia::Brisbane;
+
+    fn brisbane(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        let time = NaiveDate::from_ymd_opt(2021, 8, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap();
+        Brisbane.from_local_datetime(&time).unwrap()
+    }
+
+    fn trip_update(
+        trip_id: &str,
+        start_date: &str,
+        stop_time_update: Vec<StopTimeUpdate>,
+    ) -> FeedEntity {
+        FeedEntity {
+            id: String::from(trip_id),
+            trip_update: Some(TripUpdate {
+                trip: TripDescriptor {
+                    trip_id: Some(String::from(trip_id)),
+                    start_date: Some(String::from(start_date)),
+                    ..Default::default()
+                },
+                stop_time_update,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn stop_time_update(stop_sequence: u32, departure: StopTimeEvent) -> StopTimeUpdate {
+        StopTimeUpdate {
+            stop_sequence: Some(stop_sequence),
+            departure: Some(departure),
+            ..Default::default()
+        }
+    }
+
+    fn delay(delay: i32) -> StopTimeEvent {
+        StopTimeEvent {
+            delay: Some(delay),
+            ..Default::default()
+        }
+    }
+
+    fn time(time: DateTime<Tz>) -> StopTimeEvent {
+        StopTimeEvent {
+            time: Some(time.timestamp()),
+            ..Default::default()
+        }
+    }
+
+    /// Departures from Cultural Centre from 08:00 on Tuesday 10 August 2021 (T1, T2 and T4).
+    fn predict(entity: Vec<FeedEntity>) -> Vec<(String, String, TimeSource, DepartureStatus)> {
+        let feed = MemoryStatic::from_feed(test_feed::feed());
+        let stops = [String::from("10795")];
+        let scheduled = scheduled_departures(&feed, &stops, brisbane(10, 8, 0), 3, None).unwrap();
+        let trip_updates = FeedMessage {
+            entity,
+            ..Default::default()
+        };
+
+        predict_departures(&feed, scheduled, &trip_updates)
+            .unwrap()
+            .into_iter()
+            .map(|departure| {
+                (
+                    departure.scheduled.trip_id,
+                    departure.expected.format("%H:%M").to_string(),
+                    departure.time_source,
+                    departure.status,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scheduled_without_updates() {
+        let departures = predict(vec![trip_update("T1", "20210809", Vec::new())]);
+        assert_eq!(departures.len(), 3);
+        assert_eq!(departures[0].1, "08:15");
+        assert!(departures
+            .iter()
+            .all(|departure| departure.2 == TimeSource::Scheduled));
+    }
+
+    #[test]
+    fn delays_carry_forward() {
+        let mut canceled = trip_update("T4", "20210810", Vec::new());
+        canceled
+            .trip_update
+            .as_mut()
+            .unwrap()
+            .trip
+            .set_schedule_relationship(TripRelationship::Canceled);
+        let mut skipped = stop_time_update(2, delay(0));
+        skipped.set_schedule_relationship(StopRelationship::Skipped);
+
+        let departures = predict(vec![
+            trip_update("T1", "20210810", vec![stop_time_update(1, delay(120))]),
+            trip_update("T2", "20210810", vec![skipped]),
+            canceled,
+        ]);
+
+        use DepartureStatus::*;
+        use TimeSource::*;
+        assert_eq!(
+            departures,
+            vec![
+                (String::from("T1"), String::from("08:17"), Realtime, Running),
+                (
+                    String::from("T2"),
+                    String::from("08:40"),
+                    Scheduled,
+                    Skipped
+                ),
+                (
+                    String::from("T4"),
+                    String::from("00:05"),
+                    Scheduled,
+                    Canceled
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn absolute_times() {
+        // T1 by stop_id at Cultural Centre, T2 three minutes late leaving Roma Street
+        let mut by_stop_id = stop_time_update(0, time(brisbane(10, 8, 20)));
+        by_stop_id.stop_sequence = None;
+        by_stop_id.stop_id = Some(String::from("10795"));
+
+        let departures = predict(vec![
+            trip_update("T1", "20210810", vec![by_stop_id]),
+            trip_update(
+                "T2",
+                "20210810",
+                vec![stop_time_update(1, time(brisbane(10, 8, 33)))],
+            ),
+        ]);
+
+        assert_eq!(departures[0].0, "T1");
+        assert_eq!(departures[0].1, "08:20");
+        assert_eq!(departures[0].2, TimeSource::Realtime);
+        assert_eq!(departures[1].0, "T2");
+        assert_eq!(departures[1].1, "08:43");
+    }
+}
@@ -0,0 +1,385 @@
+//! Local estimation of departure times from vehicle positions, for trips the GTFS-RT trip updates
+//! don't cover.
+//!
+//! Each vehicle position is matched onto the shape of its trip (see ```map_match```) and compared
+//! with the time the trip is scheduled to pass that point, interpolating the scheduled running
+//! time between the stops either side. The lateness found is smoothed over the positions seen and
+//! carried forward to the stops ahead of the vehicle. Departures without a prediction from the
+//! trip updates are given these estimates, flagged as ```TimeSource::Estimated```.
+
+use crate::gtfs::gtfs_real_time::FeedMessage;
+use crate::gtfs::gtfs_static::calendar::service_day_start;
+use crate::gtfs::gtfs_static::departures::ScheduledDeparture;
+use crate::gtfs::gtfs_static::models::StopTime;
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use crate::requests::departures::{Departure, DepartureStatus, TimeSource};
+use crate::requests::map_match::{MapMatchError, MapMatcher};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+/// Tuning of an ```ArrivalEstimator```.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EstimatorConfig {
+    /// Weight (0 to 1) of the latest position in a trip's smoothed lateness, 1 to only use the
+    /// latest position.
+    pub smoothing: f64,
+    /// Positions further than this from the trip's shape, in kilometres, are ignored.
+    pub max_offset_km: f32,
+    /// Estimates are no longer given once the vehicle hasn't been seen for this long, in seconds.
+    pub max_age: i64,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        EstimatorConfig {
+            smoothing: 0.5,
+            max_offset_km: 0.3,
+            max_age: 300,
+        }
+    }
+}
+
+/// Lateness observed of a trip.
+#[derive(Debug, Clone, Copy)]
+struct TripLateness {
+    service_date: NaiveDate,
+    /// POSIX time of the latest position.
+    observed: i64,
+    /// Smoothed lateness, in seconds.
+    lateness: f64,
+    /// Stop sequence of the next stop the vehicle will reach.
+    next_stop_sequence: i32,
+}
+
+/// Estimates departure times from the vehicle positions seen.
+#[derive(Debug, Clone)]
+pub struct ArrivalEstimator {
+    config: EstimatorConfig,
+    matcher: MapMatcher,
+    stop_times: HashMap<String, Vec<StopTime>>,
+    estimates: Estimates,
+    last_feed: Option<u64>,
+}
+
+/// Lateness of the trips observed by an ```ArrivalEstimator```, which departures are estimated
+/// from. Cheap to clone, unlike the estimator, so may be shared while the estimator observes.
+#[derive(Debug, Clone)]
+pub struct Estimates {
+    max_age: i64,
+    trips: HashMap<String, TripLateness>,
+}
+
+impl ArrivalEstimator {
+    pub fn new(config: EstimatorConfig) -> Self {
+        let estimates = Estimates {
+            max_age: config.max_age,
+            trips: HashMap::new(),
+        };
+        ArrivalEstimator {
+            config,
+            matcher: MapMatcher::new(),
+            stop_times: HashMap::new(),
+            estimates,
+            last_feed: None,
+        }
+    }
+
+    /// Estimates from the positions observed so far.
+    pub fn estimates(&self) -> &Estimates {
+        &self.estimates
+    }
+
+    /// Update the lateness of trips from the positions of a vehicle positions feed. Positions
+    /// without a timestamp are taken to be from the feed's timestamp, or ```now```. A feed with
+    /// the same timestamp as the previous feed, and positions which can't be matched onto their
+    /// trip, are ignored.
+    pub fn observe<S: GtfsStatic + ?Sized>(
+        &mut self,
+        gtfs: &S,
+        vehicle_positions: &FeedMessage,
+        now: DateTime<Tz>,
+    ) -> Result<(), GtfsStaticError> {
+        let feed_timestamp = vehicle_positions.header.timestamp;
+        if feed_timestamp.is_some() && feed_timestamp == self.last_feed {
+            return Ok(());
+        }
+        self.last_feed = feed_timestamp;
+
+        let tz = now.timezone();
+        for vehicle in vehicle_positions
+            .entity
+            .iter()
+            .filter(|entity| !entity.is_deleted())
+            .filter_map(|entity| entity.vehicle.as_ref())
+        {
+            let trip = match &vehicle.trip {
+                Some(trip) => trip,
+                None => continue,
+            };
+            let trip_id = match &trip.trip_id {
+                Some(trip_id) => trip_id,
+                None => continue,
+            };
+            let observed = vehicle
+                .timestamp
+                .or(feed_timestamp)
+                .map_or(now.timestamp(), |timestamp| timestamp as i64);
+            let previous = self.estimates.trips.get(trip_id).copied();
+            if previous.filter(|trip| trip.observed >= observed).is_some() {
+                continue;
+            }
+
+            let progress = match self.matcher.match_vehicle(gtfs, vehicle) {
+                Ok(progress) => progress,
+                Err(MapMatchError::StaticError(e)) => return Err(e),
+                Err(_) => continue,
+            };
+            if progress.offset_km > self.config.max_offset_km {
+                continue;
+            }
+            let next_stop = match progress.next_stop {
+                Some(next_stop) => next_stop,
+                // the trip has finished
+                None => {
+                    self.forget(trip_id);
+                    continue;
+                }
+            };
+
+            if !self.stop_times.contains_key(trip_id) {
+                let stop_times = gtfs.stop_times_for_trip(trip_id)?;
+                self.stop_times.insert(trip_id.clone(), stop_times);
+            }
+            let stop_times = &self.stop_times[trip_id];
+            let stop_time = |sequence: i32| {
+                stop_times
+                    .iter()
+                    .find(|stop_time| stop_time.stop_sequence == sequence)
+            };
+            let arrival = stop_time(next_stop.stop_sequence)
+                .and_then(|stop_time| stop_time.arrival_time.or(stop_time.departure_time));
+            let departure = match &progress.previous_stop {
+                Some(previous_stop) => stop_time(previous_stop.stop_sequence)
+                    .and_then(|stop_time| stop_time.departure_time.or(stop_time.arrival_time)),
+                None => arrival,
+            };
+            // time the trip is scheduled at the vehicle's position, relative to the service day
+            let scheduled = match (departure, arrival) {
+                (Some(departure), Some(arrival)) => {
+                    let running = f64::from(arrival.seconds() - departure.seconds());
+                    f64::from(departure.seconds()) + f64::from(progress.fraction) * running
+                }
+                // stops without times are not interpolated
+                _ => continue,
+            };
+
+            let lateness = |date: NaiveDate| {
+                (observed - service_day_start(date, &tz).timestamp()) as f64 - scheduled
+            };
+            let start_date = trip
+                .start_date
+                .as_ref()
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok());
+            let service_date = match start_date {
+                Some(start_date) => start_date,
+                // the trip may have started on the previous service day
+                None => {
+                    let today = tz
+                        .timestamp_opt(observed, 0)
+                        .single()
+                        .unwrap_or(now)
+                        .naive_local()
+                        .date();
+                    let yesterday = today.pred_opt().unwrap_or(today);
+                    if lateness(yesterday).abs() < lateness(today).abs() {
+                        yesterday
+                    } else {
+                        today
+                    }
+                }
+            };
+
+            let mut observed_lateness = lateness(service_date);
+            if progress.previous_stop.is_none() {
+                // waiting to start the trip is only late once past the scheduled time
+                observed_lateness = observed_lateness.max(0.0);
+            }
+            let lateness = match previous {
+                Some(previous) if previous.service_date == service_date => {
+                    previous.lateness
+                        + self.config.smoothing * (observed_lateness - previous.lateness)
+                }
+                _ => observed_lateness,
+            };
+            self.estimates.trips.insert(
+                trip_id.clone(),
+                TripLateness {
+                    service_date,
+                    observed,
+                    lateness,
+                    next_stop_sequence: next_stop.stop_sequence,
+                },
+            );
+        }
+
+        let max_age = self.config.max_age;
+        let expired: Vec<String> = self
+            .estimates
+            .trips
+            .iter()
+            .filter(|(_, trip)| now.timestamp() - trip.observed > max_age)
+            .map(|(trip_id, _)| trip_id.clone())
+            .collect();
+        for trip_id in expired {
+            self.forget(&trip_id);
+        }
+        Ok(())
+    }
+
+    fn forget(&mut self, trip_id: &str) {
+        self.estimates.trips.remove(trip_id);
+        self.stop_times.remove(trip_id);
+        self.matcher.remove(trip_id);
+    }
+}
+
+impl Estimates {
+    /// Estimated time of ```departure``` as of ```now```, if its vehicle has been seen recently
+    /// and hasn't yet passed the departure stop.
+    pub fn estimate(
+        &self,
+        departure: &ScheduledDeparture,
+        now: DateTime<Tz>,
+    ) -> Option<DateTime<Tz>> {
+        let trip = self.trips.get(&departure.trip_id)?;
+        if trip.service_date != departure.service_date
+            || departure.stop_sequence < trip.next_stop_sequence
+            || now.timestamp() - trip.observed > self.max_age
+        {
+            return None;
+        }
+
+        let expected = departure.departure + Duration::seconds(trip.lateness.round() as i64);
+        // the vehicle can't be at the stop before it was last seen short of it
+        let observed = now.timezone().timestamp_opt(trip.observed, 0).single()?;
+        Some(expected.max(observed))
+    }
+
+    /// Give the running ```departures``` without a prediction their estimated time, returning
+    /// them ordered by expected time.
+    pub fn apply(&self, mut departures: Vec<Departure>, now: DateTime<Tz>) -> Vec<Departure> {
+        for departure in departures.iter_mut().filter(|departure| {
+            departure.time_source == TimeSource::Scheduled
+                && departure.status == DepartureStatus::Running
+        }) {
+            if let Some(expected) = self.estimate(&departure.scheduled, now) {
+                departure.expected = expected;
+                departure.time_source = TimeSource::Estimated;
+            }
+        }
+        departures.sort_by_key(|departure| departure.expected);
+        departures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::{
+        FeedEntity, FeedHeader, Position, TripDescriptor, VehiclePosition,
+    };
+    use crate::gtfs::gtfs_static::departures::scheduled_departures;
+    use crate::gtfs::gtfs_static::memory::MemoryStatic;
+    use crate::gtfs::gtfs_static::test_feed;
+    use crate::requests::departures::predict_departures;
+    use chrono_tz::Australia::Brisbane;
+
+    fn brisbane(day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Tz> {
+        let time = NaiveDate::from_ymd_opt(2021, 8, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, second))
+            .unwrap();
+        Brisbane.from_local_datetime(&time).unwrap()
+    }
+
+    /// Feed with the position of the vehicle running T1, without a start date.
+    fn vehicle_positions(time: DateTime<Tz>, latitude: f32, longitude: f32) -> FeedMessage {
+        FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: String::from("2.0"),
+                timestamp: Some(time.timestamp() as u64),
+                ..Default::default()
+            },
+            entity: vec![FeedEntity {
+                id: String::from("V1"),
+                vehicle: Some(VehiclePosition {
+                    trip: Some(TripDescriptor {
+                        trip_id: Some(String::from("T1")),
+                        ..Default::default()
+                    }),
+                    position: Some(Position {
+                        latitude,
+                        longitude,
+                        ..Default::default()
+                    }),
+                    timestamp: Some(time.timestamp() as u64),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn estimates_from_lateness() {
+        let gtfs = MemoryStatic::from_feed(test_feed::feed());
+        let mut estimator = ArrivalEstimator::new(EstimatorConfig::default());
+        // departures from Roma Street from 08:00 on Tuesday 10 August 2021 (T1 and T2)
+        let departures = |estimator: &ArrivalEstimator, now: DateTime<Tz>| {
+            let stops = [String::from("600029")];
+            let scheduled =
+                scheduled_departures(&gtfs, &stops, brisbane(10, 8, 0, 0), 2, None).unwrap();
+            let departures = predict_departures(&gtfs, scheduled, &FeedMessage::default()).unwrap();
+            estimator
+                .estimates()
+                .apply(departures, now)
+                .into_iter()
+                .map(|departure| {
+                    (
+                        departure.scheduled.trip_id,
+                        departure.expected.format("%H:%M").to_string(),
+                        departure.time_source,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // T1 is half way to Cultural Centre (scheduled at about 08:07:45), two and a half
+        // minutes late
+        let now = brisbane(10, 8, 10, 15);
+        let feed = vehicle_positions(now, -27.4840, 153.0102);
+        estimator.observe(&gtfs, &feed, now).unwrap();
+        use TimeSource::*;
+        assert_eq!(
+            departures(&estimator, now),
+            vec![
+                (String::from("T1"), String::from("08:27"), Estimated),
+                (String::from("T2"), String::from("08:30"), Scheduled),
+            ]
+        );
+
+        // four and a half minutes late leaving Cultural Centre, smoothed to three and a half
+        let now = brisbane(10, 8, 19, 30);
+        let feed = vehicle_positions(now, -27.4724, 153.0183);
+        estimator.observe(&gtfs, &feed, now).unwrap();
+        let estimated = departures(&estimator, now);
+        assert_eq!(estimated[0].1, "08:28");
+
+        // the estimate expires once the vehicle is no longer seen
+        let now = now + Duration::seconds(301);
+        estimator
+            .observe(&gtfs, &FeedMessage::default(), now)
+            .unwrap();
+        assert_eq!(departures(&estimator, now)[0].2, Scheduled);
+    }
+}
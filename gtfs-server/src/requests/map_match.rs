@@ -0,0 +1,396 @@
+//! Map matching of vehicle positions onto the shapes of their trips.
+//!
+//! A vehicle's position is snapped onto the shape of its trip (from shapes.txt, or a straight
+//! line between its stops for trips without a shape) to find how far along the trip it is, the
+//! stops it is between and how far between them, without relying on the feed's
+//! ```current_stop_sequence```. When the feed does give the stop sequence it chooses between
+//! parts of a shape which pass the same place, such as a loop, and whether that stop has been
+//! reached is taken from the feed's ```current_status```.
+
+use crate::geo::{self, Point};
+use crate::gtfs::gtfs_real_time::vehicle_position::VehicleStopStatus;
+use crate::gtfs::gtfs_real_time::VehiclePosition;
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use std::collections::HashMap;
+
+/// Map matching associated errors.
+#[derive(Debug)]
+pub enum MapMatchError {
+    /// The vehicle position has no trip id.
+    NoTrip,
+    /// The vehicle position has no coordinates.
+    NoPosition,
+    UnknownTrip(String),
+    UnknownStop(String),
+    /// The trip has neither a shape nor two stops to draw one between.
+    NoShape(String),
+    StaticError(GtfsStaticError),
+}
+
+impl std::error::Error for MapMatchError {}
+
+impl std::fmt::Display for MapMatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use MapMatchError::*;
+        match self {
+            NoTrip => write!(f, "Vehicle position has no trip"),
+            NoPosition => write!(f, "Vehicle position has no coordinates"),
+            UnknownTrip(trip_id) => write!(f, "Trip {:} is not in the static feed", trip_id),
+            UnknownStop(stop_id) => write!(f, "Stop {:} is not in the static feed", stop_id),
+            NoShape(trip_id) => write!(f, "Trip {:} has no shape", trip_id),
+            StaticError(e) => write!(f, "Error querying static feed\n{:}", e),
+        }
+    }
+}
+
+impl From<GtfsStaticError> for MapMatchError {
+    fn from(e: GtfsStaticError) -> Self {
+        MapMatchError::StaticError(e)
+    }
+}
+
+/// A stop of a trip, located on the trip's shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopOnShape {
+    pub stop_id: String,
+    pub stop_sequence: i32,
+    /// Distance along the shape, in kilometres.
+    pub distance_km: f32,
+}
+
+/// Where a vehicle is along its trip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripProgress {
+    pub trip_id: String,
+    /// Distance along the trip's shape, in kilometres.
+    pub distance_km: f32,
+    /// Distance of the vehicle from the shape, in kilometres.
+    pub offset_km: f32,
+    /// Last stop reached, or ```None``` before the first stop.
+    pub previous_stop: Option<StopOnShape>,
+    /// Next stop to be reached, or ```None``` from the last stop on.
+    pub next_stop: Option<StopOnShape>,
+    /// Fraction (0 to 1) of the way from the previous stop to the next, 0 before the first stop
+    /// and 1 from the last stop on.
+    pub fraction: f32,
+}
+
+/// Shape of a trip with its stops located along it.
+#[derive(Debug, Clone)]
+pub struct TripShape {
+    trip_id: String,
+    // points of the shape with their distance along the shape, in kilometres
+    points: Vec<(Point, f64)>,
+    stops: Vec<StopOnShape>,
+}
+
+impl TripShape {
+    /// Shape of ```trip_id``` from the static feed. Stops are located at the nearest point of the
+    /// shape past the previous stop.
+    pub fn for_trip<S: GtfsStatic + ?Sized>(
+        gtfs: &S,
+        trip_id: &str,
+    ) -> Result<Self, MapMatchError> {
+        let trip = gtfs
+            .trip(trip_id)?
+            .ok_or_else(|| MapMatchError::UnknownTrip(String::from(trip_id)))?;
+        let stop_times = gtfs.stop_times_for_trip(trip_id)?;
+        let mut coordinates = Vec::with_capacity(stop_times.len());
+        for stop_time in &stop_times {
+            let stop = gtfs
+                .stop(&stop_time.stop_id)?
+                .ok_or_else(|| MapMatchError::UnknownStop(stop_time.stop_id.clone()))?;
+            coordinates.push(Point::from_f32(stop.stop_lat, stop.stop_lon));
+        }
+
+        let shape = match &trip.shape_id {
+            Some(shape_id) => gtfs.shape(shape_id)?,
+            None => Vec::new(),
+        };
+        let vertices: Vec<Point> = if shape.len() >= 2 {
+            shape
+                .iter()
+                .map(|point| Point::from_f32(point.shape_pt_lat, point.shape_pt_lon))
+                .collect()
+        } else {
+            // without a shape, the vehicle is assumed to travel straight between stops
+            coordinates.clone()
+        };
+        if vertices.len() < 2 {
+            return Err(MapMatchError::NoShape(String::from(trip_id)));
+        }
+
+        let mut points = Vec::with_capacity(vertices.len());
+        let mut distance = 0.0;
+        for (i, &point) in vertices.iter().enumerate() {
+            if i > 0 {
+                distance += geo::distance_km(vertices[i - 1], point);
+            }
+            points.push((point, distance));
+        }
+
+        let mut trip_shape = TripShape {
+            trip_id: String::from(trip_id),
+            points,
+            stops: Vec::with_capacity(stop_times.len()),
+        };
+        let mut previous = 0.0;
+        for (stop_time, &point) in stop_times.iter().zip(coordinates.iter()) {
+            let (distance, _) = trip_shape.project(point, previous, f64::INFINITY);
+            trip_shape.stops.push(StopOnShape {
+                stop_id: stop_time.stop_id.clone(),
+                stop_sequence: stop_time.stop_sequence,
+                distance_km: distance as f32,
+            });
+            previous = distance;
+        }
+        Ok(trip_shape)
+    }
+
+    pub fn trip_id(&self) -> &str {
+        &self.trip_id
+    }
+
+    /// Stops of the trip, in order of stop sequence.
+    pub fn stops(&self) -> &[StopOnShape] {
+        &self.stops
+    }
+
+    /// Length of the shape, in kilometres.
+    pub fn length_km(&self) -> f32 {
+        self.points.last().map_or(0.0, |point| point.1) as f32
+    }
+
+    /// Progress along the trip of a vehicle at ```lat```, ```lon```. If the vehicle's
+    /// ```current_stop_sequence``` is known, the vehicle is matched at that stop when
+    /// ```current_status``` is ```StoppedAt```, and otherwise onto the part of the shape leading
+    /// up to it, with that stop still to be reached.
+    pub fn locate(
+        &self,
+        lat: f32,
+        lon: f32,
+        current_stop_sequence: Option<u32>,
+        current_status: VehicleStopStatus,
+    ) -> TripProgress {
+        let mut range = (0.0, f64::INFINITY);
+        let current = current_stop_sequence.and_then(|sequence| {
+            self.stops
+                .iter()
+                .position(|stop| stop.stop_sequence as i64 == sequence as i64)
+        });
+        let mut approaching = None;
+        if let Some(i) = current {
+            let at = self.stops[i].distance_km as f64;
+            range = if current_status == VehicleStopStatus::StoppedAt {
+                (at, at)
+            } else {
+                approaching = Some(i);
+                let from = if i > 0 {
+                    self.stops[i - 1].distance_km
+                } else {
+                    0.0
+                };
+                (from as f64, at)
+            };
+        }
+        let (distance, offset) = self.project(Point::from_f32(lat, lon), range.0, range.1);
+        let distance = distance as f32;
+
+        let mut next = self
+            .stops
+            .iter()
+            .position(|stop| stop.distance_km > distance)
+            .unwrap_or(self.stops.len());
+        if let Some(i) = approaching {
+            // matched up to, but not at, the stop being approached
+            next = next.min(i);
+        }
+        let previous_stop = next.checked_sub(1).map(|i| self.stops[i].clone());
+        let next_stop = self.stops.get(next).cloned();
+        let fraction = match (&previous_stop, &next_stop) {
+            (Some(previous), Some(next)) => {
+                let between = next.distance_km - previous.distance_km;
+                ((distance - previous.distance_km) / between).clamp(0.0, 1.0)
+            }
+            (None, _) => 0.0,
+            (_, None) => 1.0,
+        };
+
+        TripProgress {
+            trip_id: self.trip_id.clone(),
+            distance_km: distance,
+            offset_km: offset as f32,
+            previous_stop,
+            next_stop,
+            fraction,
+        }
+    }
+
+    /// Nearest point of the shape between ```from``` and ```to``` kilometres along it to
+    /// ```point```, as (distance along the shape, distance from the shape).
+    fn project(&self, point: Point, from: f64, to: f64) -> (f64, f64) {
+        let mut nearest = (from.min(self.length_km() as f64), f64::INFINITY);
+        for segment in self.points.windows(2) {
+            let ((start, start_km), (end, end_km)) = (segment[0], segment[1]);
+            if end_km < from || start_km > to {
+                continue;
+            }
+
+            let projection = geo::project_onto_segment(point, start, end);
+            // only the part of the segment between from and to
+            let along = projection
+                .along_km
+                .max(from - start_km)
+                .min(to - start_km)
+                .min(end_km - start_km)
+                .max(0.0);
+            let offset = if along == projection.along_km {
+                projection.distance_km
+            } else {
+                let nearest_point =
+                    geo::destination(start, geo::initial_bearing(start, end), along);
+                geo::distance_km(point, nearest_point)
+            };
+            if offset < nearest.1 {
+                nearest = (start_km + along, offset);
+            }
+        }
+        nearest
+    }
+}
+
+/// Matches vehicle positions onto their trips, keeping the shape of each trip seen.
+#[derive(Debug, Clone, Default)]
+pub struct MapMatcher {
+    shapes: HashMap<String, TripShape>,
+}
+
+impl MapMatcher {
+    pub fn new() -> Self {
+        MapMatcher::default()
+    }
+
+    /// Progress of ```vehicle``` along its trip.
+    pub fn match_vehicle<S: GtfsStatic + ?Sized>(
+        &mut self,
+        gtfs: &S,
+        vehicle: &VehiclePosition,
+    ) -> Result<TripProgress, MapMatchError> {
+        let trip_id = vehicle
+            .trip
+            .as_ref()
+            .and_then(|trip| trip.trip_id.as_deref())
+            .ok_or(MapMatchError::NoTrip)?;
+        let position = vehicle.position.as_ref().ok_or(MapMatchError::NoPosition)?;
+        if !self.shapes.contains_key(trip_id) {
+            let shape = TripShape::for_trip(gtfs, trip_id)?;
+            self.shapes.insert(String::from(trip_id), shape);
+        }
+        Ok(self.shapes[trip_id].locate(
+            position.latitude,
+            position.longitude,
+            vehicle.current_stop_sequence,
+            vehicle.current_status(),
+        ))
+    }
+
+    /// Forget the shape of ```trip_id```, such as once the trip has finished.
+    pub fn remove(&mut self, trip_id: &str) {
+        self.shapes.remove(trip_id);
+    }
+
+    /// Forget the shapes of the trips seen, such as after the static feed is updated.
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::{Position, TripDescriptor};
+    use crate::gtfs::gtfs_static::memory::MemoryStatic;
+    use crate::gtfs::gtfs_static::test_feed;
+
+    fn vehicle(trip_id: &str, latitude: f32, longitude: f32) -> VehiclePosition {
+        VehiclePosition {
+            trip: Some(TripDescriptor {
+                trip_id: Some(String::from(trip_id)),
+                ..Default::default()
+            }),
+            position: Some(Position {
+                latitude,
+                longitude,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn stop_id(stop: &Option<StopOnShape>) -> Option<&str> {
+        stop.as_ref().map(|stop| stop.stop_id.as_str())
+    }
+
+    #[test]
+    fn locates_vehicles_along_shapes() {
+        let gtfs = MemoryStatic::from_feed(test_feed::feed());
+        let mut matcher = MapMatcher::new();
+
+        // T1 follows the shape west of the straight line between UQ Lakes and Cultural Centre
+        let progress = matcher
+            .match_vehicle(&gtfs, &vehicle("T1", -27.4840, 153.0102))
+            .unwrap();
+        assert_eq!(stop_id(&progress.previous_stop), Some("1882"));
+        assert_eq!(stop_id(&progress.next_stop), Some("10795"));
+        assert!(progress.offset_km < 0.05);
+        assert!(progress.fraction > 0.4 && progress.fraction < 0.7);
+        let shape = TripShape::for_trip(&gtfs, "T1").unwrap();
+        assert!(shape.stops()[1].distance_km > 3.0);
+        assert_eq!(shape.stops()[2].distance_km, shape.length_km());
+
+        // at the last stop
+        let progress = matcher
+            .match_vehicle(&gtfs, &vehicle("T1", -27.4656, 153.0193))
+            .unwrap();
+        assert_eq!(stop_id(&progress.previous_stop), Some("600029"));
+        assert_eq!(progress.next_stop, None);
+        assert_eq!(progress.fraction, 1.0);
+
+        // T2 has no shape, so runs straight between its stops
+        let progress = matcher
+            .match_vehicle(&gtfs, &vehicle("T2", -27.4690, 153.0188))
+            .unwrap();
+        assert_eq!(stop_id(&progress.previous_stop), Some("600029"));
+        assert_eq!(stop_id(&progress.next_stop), Some("10795"));
+        assert!((progress.fraction - 0.5).abs() < 0.05);
+
+        assert!(matches!(
+            matcher.match_vehicle(&gtfs, &vehicle("T9", -27.4690, 153.0188)),
+            Err(MapMatchError::UnknownTrip(_))
+        ));
+    }
+
+    #[test]
+    fn current_stop_sequence_constrains_match() {
+        let gtfs = MemoryStatic::from_feed(test_feed::feed());
+        let shape = TripShape::for_trip(&gtfs, "T1").unwrap();
+
+        use VehicleStopStatus::*;
+        // a vehicle just past Cultural Centre, which the feed says hasn't reached it yet
+        let progress = shape.locate(-27.4715, 153.0184, Some(2), InTransitTo);
+        assert_eq!(progress.distance_km, shape.stops()[1].distance_km);
+        assert_eq!(stop_id(&progress.previous_stop), Some("1882"));
+        assert_eq!(stop_id(&progress.next_stop), Some("10795"));
+        assert!(progress.offset_km > 0.05);
+
+        // or is stopped at it
+        let progress = shape.locate(-27.4715, 153.0184, Some(2), StoppedAt);
+        assert_eq!(stop_id(&progress.previous_stop), Some("10795"));
+        assert_eq!(stop_id(&progress.next_stop), Some("600029"));
+        assert_eq!(progress.fraction, 0.0);
+
+        let progress = shape.locate(-27.4715, 153.0184, None, InTransitTo);
+        assert_eq!(stop_id(&progress.next_stop), Some("600029"));
+        assert!(progress.offset_km < 0.01);
+    }
+}
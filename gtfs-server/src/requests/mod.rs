@@ -1,3 +1,8 @@
 //! Processing of requests.
 
 pub mod closest_vehicle;
+pub mod departures;
+pub mod estimator;
+pub mod map_match;
+pub mod onboard;
+pub mod protocol;
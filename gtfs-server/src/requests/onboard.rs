@@ -0,0 +1,345 @@
+//! Detection of the vehicle a device is onboard, from a trail of the device's GPS fixes.
+//!
+//! Each fix is compared to the vehicle positions current at the time, scoring every nearby
+//! vehicle by its distance from the device and by how well the vehicle's reported bearing and
+//! speed agree with the device's movement since its previous fix. A vehicle's score is the mean
+//! of its scores over a sliding window of fixes, so only vehicles that stay with the device
+//! score highly. The most likely vehicle is kept until another scores clearly higher, and
+//! through short gaps in either the device's fixes or the vehicle's positions.
+
+use crate::geo::{self, Point};
+use crate::gtfs::gtfs_real_time::FeedEntity;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Scale of the distance between the device and a vehicle, in kilometres. A vehicle this far from
+/// the device scores 1/e of an adjacent vehicle.
+const DISTANCE_SCALE_KM: f32 = 0.05;
+/// Scale of the difference between the device and vehicle speeds, in metres per second.
+const SPEED_SCALE_MPS: f32 = 3.0;
+/// Movement of the device below which its heading and speed are not compared, as they are
+/// dominated by GPS noise.
+const MIN_MOVEMENT_KM: f32 = 0.01;
+
+/// A GPS fix of the device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceFix {
+    /// Time of the fix, in POSIX time.
+    pub timestamp: u64,
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+/// Tuning of an ```OnboardTracker```.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackerConfig {
+    /// Length of the sliding window of fixes scored, in seconds.
+    pub window: u64,
+    /// Vehicles further than this from a fix, in kilometres, aren't candidates for it.
+    pub max_distance_km: f32,
+    /// Longest gap, in seconds, in the device's fixes or a vehicle's positions which doesn't
+    /// interrupt tracking. After a longer gap in the device's fixes tracking starts afresh.
+    pub dropout: u64,
+    /// Score (0 to 1) a vehicle needs to be reported.
+    pub min_score: f32,
+    /// How much higher another vehicle must score to replace the reported vehicle.
+    pub switch_margin: f32,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        TrackerConfig {
+            window: 120,
+            max_distance_km: 0.5,
+            dropout: 60,
+            min_score: 0.3,
+            switch_margin: 0.15,
+        }
+    }
+}
+
+/// The vehicle a device is most likely onboard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnboardVehicle {
+    /// Vehicle id of the GTFS-RT feed, or the entity id if the vehicle has no id.
+    pub vehicle_id: String,
+    /// Confidence (0 to 1) that the device is onboard, which is higher the higher the vehicle
+    /// scores and the lower other vehicles score.
+    pub confidence: f32,
+}
+
+/// Score of a vehicle for a single fix.
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    timestamp: u64,
+    score: f32,
+    // whether the vehicle was in the feed for the fix, rather than carried over a dropout
+    observed: bool,
+}
+
+/// Tracks the vehicle a device is onboard from its fixes.
+#[derive(Debug, Clone)]
+pub struct OnboardTracker {
+    config: TrackerConfig,
+    fixes: VecDeque<DeviceFix>,
+    candidates: HashMap<String, VecDeque<Observation>>,
+    current: Option<String>,
+}
+
+impl OnboardTracker {
+    pub fn new(config: TrackerConfig) -> Self {
+        OnboardTracker {
+            config,
+            fixes: VecDeque::new(),
+            candidates: HashMap::new(),
+            current: None,
+        }
+    }
+
+    /// Add a fix of the device, scoring it against the ```vehicles``` (entities of a vehicle
+    /// positions feed) current at the time of the fix, and return the vehicle the device is most
+    /// likely onboard. Fixes older than the previous fix are ignored.
+    pub fn update(&mut self, fix: DeviceFix, vehicles: &[FeedEntity]) -> Option<OnboardVehicle> {
+        let previous = self.fixes.back().copied();
+        match previous {
+            Some(previous) if fix.timestamp <= previous.timestamp => return self.onboard(),
+            Some(previous) if fix.timestamp - previous.timestamp > self.config.dropout => {
+                self.fixes.clear();
+                self.candidates.clear();
+                self.current = None;
+            }
+            _ => (),
+        }
+
+        let motion = self
+            .fixes
+            .back()
+            .and_then(|previous| motion(previous, &fix));
+        let mut scored = HashMap::new();
+        let mut present = HashSet::new();
+        for entity in vehicles {
+            let vehicle = match &entity.vehicle {
+                Some(vehicle) => vehicle,
+                None => continue,
+            };
+            let position = match &vehicle.position {
+                Some(position) => position,
+                None => continue,
+            };
+            let id = vehicle
+                .vehicle
+                .as_ref()
+                .and_then(|descriptor| descriptor.id.as_deref())
+                .unwrap_or(&entity.id);
+            present.insert(id);
+
+            let distance = geo::distance_km(
+                Point::from_f32(fix.latitude, fix.longitude),
+                Point::from_f32(position.latitude, position.longitude),
+            ) as f32;
+            if distance > self.config.max_distance_km {
+                continue;
+            }
+
+            let mut score = (-distance / DISTANCE_SCALE_KM).exp();
+            if let Some((heading, speed)) = motion {
+                if let Some(bearing) = position.bearing {
+                    let difference = (heading - bearing).to_radians();
+                    score *= 0.5 + 0.25 * (1.0 + difference.cos());
+                }
+                if let Some(vehicle_speed) = position.speed {
+                    score *= 0.5 + 0.5 * (-(speed - vehicle_speed).abs() / SPEED_SCALE_MPS).exp();
+                }
+            }
+            scored.insert(String::from(id), score);
+        }
+
+        for (id, observations) in self.candidates.iter_mut() {
+            match scored.remove(id) {
+                Some(score) => observations.push_back(Observation {
+                    timestamp: fix.timestamp,
+                    score,
+                    observed: true,
+                }),
+                // moved out of range
+                None if present.contains(id.as_str()) => (),
+                None => {
+                    // the vehicle's position may be missing from a feed, so keep its last score
+                    // through a short dropout
+                    let last_observed = observations.iter().rev().find(|o| o.observed).copied();
+                    if let Some(last) = last_observed {
+                        if fix.timestamp - last.timestamp <= self.config.dropout {
+                            observations.push_back(Observation {
+                                timestamp: fix.timestamp,
+                                score: last.score,
+                                observed: false,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        for (id, score) in scored {
+            let observation = Observation {
+                timestamp: fix.timestamp,
+                score,
+                observed: true,
+            };
+            self.candidates.insert(id, vec![observation].into());
+        }
+        self.fixes.push_back(fix);
+
+        // slide the window
+        let start = fix.timestamp.saturating_sub(self.config.window);
+        while self.fixes.front().filter(|f| f.timestamp < start).is_some() {
+            self.fixes.pop_front();
+        }
+        self.candidates.retain(|_, observations| {
+            while observations
+                .front()
+                .filter(|o| o.timestamp < start)
+                .is_some()
+            {
+                observations.pop_front();
+            }
+            !observations.is_empty()
+        });
+
+        self.choose();
+        self.onboard()
+    }
+
+    /// The vehicle the device is most likely onboard, as of the latest fix.
+    pub fn onboard(&self) -> Option<OnboardVehicle> {
+        let vehicle_id = self.current.clone()?;
+        let scores = self.scores();
+        let total: f32 = scores.values().sum();
+        let score = scores.get(&vehicle_id).copied().unwrap_or(0.0);
+        Some(OnboardVehicle {
+            vehicle_id,
+            confidence: if total > 0.0 {
+                score * score / total
+            } else {
+                0.0
+            },
+        })
+    }
+
+    /// Mean score of each candidate over the window, counting fixes without an observation of
+    /// the candidate as 0.
+    fn scores(&self) -> HashMap<String, f32> {
+        let fixes = self.fixes.len().max(1) as f32;
+        self.candidates
+            .iter()
+            .map(|(id, observations)| {
+                let total: f32 = observations.iter().map(|o| o.score).sum();
+                (id.clone(), total / fixes)
+            })
+            .collect()
+    }
+
+    /// Update the reported vehicle, only replacing it if another vehicle scores clearly higher.
+    fn choose(&mut self) {
+        let scores = self.scores();
+        let best = scores
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(id, score)| (id.clone(), *score));
+        let current = self
+            .current
+            .as_ref()
+            .and_then(|id| scores.get(id))
+            .copied()
+            .unwrap_or(0.0);
+
+        self.current = match best {
+            // the current vehicle is kept through a dip in its score
+            Some((_, score)) if current > 0.0 && score <= current + self.config.switch_margin => {
+                self.current.take()
+            }
+            Some((id, score)) if score >= self.config.min_score => Some(id),
+            _ => None,
+        };
+    }
+}
+
+/// Heading (degrees clockwise from north) and speed (metres per second) of the device moving
+/// from ```from``` to ```to```, if it moved enough for them to be meaningful.
+fn motion(from: &DeviceFix, to: &DeviceFix) -> Option<(f32, f32)> {
+    let (from_point, to_point) = (
+        Point::from_f32(from.latitude, from.longitude),
+        Point::from_f32(to.latitude, to.longitude),
+    );
+    let distance = geo::distance_km(from_point, to_point) as f32;
+    if distance < MIN_MOVEMENT_KM || to.timestamp <= from.timestamp {
+        return None;
+    }
+
+    let heading = geo::initial_bearing(from_point, to_point) as f32;
+    let speed = distance * 1000.0 / (to.timestamp - from.timestamp) as f32;
+    Some((heading, speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::{Position, VehicleDescriptor, VehiclePosition};
+
+    /// Vehicle travelling north.
+    fn vehicle(id: &str, latitude: f32, longitude: f32, speed: f32) -> FeedEntity {
+        FeedEntity {
+            id: String::from(id),
+            vehicle: Some(VehiclePosition {
+                vehicle: Some(VehicleDescriptor {
+                    id: Some(String::from(id)),
+                    ..Default::default()
+                }),
+                position: Some(Position {
+                    latitude,
+                    longitude,
+                    bearing: Some(0.0),
+                    speed: Some(speed),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tracks_vehicle_through_dropouts() {
+        let mut tracker = OnboardTracker::new(TrackerConfig::default());
+        // 10 m/s north is 0.00009 degrees of latitude a second
+        let latitude = |time: u64| -27.5 + 0.00009 * time as f32;
+        let fix = |time: u64| DeviceFix {
+            timestamp: 1000 + time,
+            latitude: latitude(time),
+            longitude: 153.0,
+        };
+        // the device travels on bus A, which passes a stationary bus B at the start
+        let vehicles = |time: u64| {
+            vec![
+                vehicle("A", latitude(time), 153.0002, 10.0),
+                vehicle("B", -27.4995, 153.0, 0.0),
+            ]
+        };
+
+        let mut onboard = None;
+        for time in (0..60).step_by(10) {
+            onboard = tracker.update(fix(time), &vehicles(time));
+        }
+        let onboard = onboard.unwrap();
+        assert_eq!(onboard.vehicle_id, "A");
+        assert!(onboard.confidence > 0.5);
+
+        // bus A is missing from the feed, then the device has no fix for 40 seconds
+        let onboard = tracker.update(fix(60), &vehicles(60)[1..]).unwrap();
+        assert_eq!(onboard.vehicle_id, "A");
+        let onboard = tracker.update(fix(100), &vehicles(100)).unwrap();
+        assert_eq!(onboard.vehicle_id, "A");
+        assert!(onboard.confidence > 0.5);
+
+        // after a long dropout tracking starts afresh
+        assert_eq!(tracker.update(fix(1000), &[]), None);
+    }
+}
@@ -0,0 +1,26 @@
+//! Messages of the departure board protocol defined in gtfs-requests.proto, exchanged between the
+//! server and embedded devices.
+
+include!(concat!(env!("OUT_DIR"), "/gtfs_requests.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn request_defaults() {
+        let request = DepartureRequest {
+            stop_id: vec![String::from("600029")],
+            ..Default::default()
+        };
+        let mut bytes = Vec::new();
+        request.encode(&mut bytes).unwrap();
+
+        let decoded = DepartureRequest::decode(&bytes[..]).unwrap();
+        assert_eq!(decoded.stop_id, vec!["600029"]);
+        assert_eq!(decoded.max_services(), 5);
+        assert_eq!(decoded.direction_id, None);
+        assert!(!decoded.fields.unwrap_or_default().expected_time());
+    }
+}
@@ -0,0 +1,127 @@
+//! HTTP API of the server.
+//!
+//! Requests are POSTed to ```/departures```, ```/closest_vehicle``` or ```/alerts``` as protocol
+//! buffer messages, or as JSON with a ```Content-Type: application/json``` header. The response,
+//! or a ```protocol::Error``` if the request fails, is returned in the same encoding.
+
+use crate::server::{run_blocking, Encoding, Server, ServerError};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Serve requests on ```address``` until an error occurs.
+pub async fn serve(server: Arc<Server>, address: SocketAddr) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let server = server.clone();
+        let service = service_fn(move |request| handle(server.clone(), request));
+        async move { Ok::<_, Infallible>(service) }
+    });
+
+    hyper::Server::bind(&address).serve(make_service).await
+}
+
+/// Process a single HTTP request.
+pub async fn handle(
+    server: Arc<Server>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let encoding = match request.headers().get(CONTENT_TYPE) {
+        Some(content_type)
+            if content_type
+                .as_bytes()
+                .starts_with(JSON_CONTENT_TYPE.as_bytes()) =>
+        {
+            Encoding::Json
+        }
+        _ => Encoding::Protobuf,
+    };
+    if request.method() != Method::POST {
+        let error = ServerError::InvalidRequest(String::from("Requests must be POSTed"));
+        return Ok(error_response(
+            encoding,
+            StatusCode::METHOD_NOT_ALLOWED,
+            &error,
+        ));
+    }
+
+    let endpoint = String::from(request.uri().path().trim_start_matches('/'));
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => {
+            let error = ServerError::InvalidRequest(e.to_string());
+            return Ok(error_response(encoding, StatusCode::BAD_REQUEST, &error));
+        }
+    };
+
+    let processed = run_blocking(server, move |server| {
+        server.handle(&endpoint, encoding, &body)
+    });
+    Ok(match processed.await.and_then(|processed| processed) {
+        Ok(body) => response(encoding, StatusCode::OK, body),
+        Err(error) => error_response(encoding, status(&error), &error),
+    })
+}
+
+/// HTTP status of a failed request.
+fn status(error: &ServerError) -> StatusCode {
+    match error {
+        ServerError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+        ServerError::UnknownStop(_) | ServerError::NotFound(_) => StatusCode::NOT_FOUND,
+        ServerError::StaticError(_) => StatusCode::SERVICE_UNAVAILABLE,
+        ServerError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn response(encoding: Encoding, status: StatusCode, body: Vec<u8>) -> Response<Body> {
+    let content_type = match encoding {
+        Encoding::Protobuf => PROTOBUF_CONTENT_TYPE,
+        Encoding::Json => JSON_CONTENT_TYPE,
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+fn error_response(encoding: Encoding, status: StatusCode, error: &ServerError) -> Response<Body> {
+    response(encoding, status, encoding.encode_error(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::protocol;
+    use crate::server::tests::server;
+
+    #[tokio::test]
+    async fn json_error_response() {
+        let request = Request::post("/departures")
+            .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+            .body(Body::from(r#"{"stop_id": ["missing"]}"#))
+            .unwrap();
+        let response = handle(Arc::new(server()), request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let error: protocol::Error = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code(), protocol::error::Code::UnknownStop);
+    }
+
+    #[tokio::test]
+    async fn requests_must_be_posted() {
+        let request = Request::get("/alerts").body(Body::empty()).unwrap();
+        let response = handle(Arc::new(server()), request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROTOBUF_CONTENT_TYPE);
+    }
+}
@@ -1,4 +1,837 @@
 //! Implementation of the server, handling incoming requests, maintaining existing requests and
 //! managing dropped connections.
+//!
+//! Requests are processed by ```Server``` independently of the transport they arrived on, with
+//! every client sharing the same static feed and snapshot of the real-time feeds. Messages are
+//! those of gtfs-requests.proto, encoded as protocol buffers or as JSON.
 
+pub mod http;
 pub mod serial;
+
+use crate::gtfs::gtfs_real_time::poller::{RealtimeReceiver, RealtimeSnapshot};
+use crate::gtfs::gtfs_real_time::translated_string::Translation;
+use crate::gtfs::gtfs_real_time::{FeedMessage, FeedType, TranslatedString};
+use crate::gtfs::gtfs_static::departures::scheduled_departures;
+use crate::gtfs::gtfs_static::types::RouteType;
+use crate::gtfs::gtfs_static::{GtfsStatic, GtfsStaticError};
+use crate::requests::closest_vehicle::find_closest;
+use crate::requests::departures::{predict_departures, DepartureStatus, TimeSource};
+use crate::requests::estimator::{ArrivalEstimator, Estimates, EstimatorConfig};
+use crate::requests::protocol;
+use crate::requests::protocol::departure::Status;
+use crate::requests::protocol::error::Code;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How long after their scheduled time services are still searched for, in case they are running
+/// late.
+const LATE_SERVICE_MINUTES: i64 = 30;
+/// Trip updates received longer ago than this are ignored, as the feed has stopped being updated,
+/// and departures are estimated from vehicle positions instead.
+const STALE_TRIP_UPDATES_MINUTES: i64 = 5;
+
+/// Server associated errors, returned to the client as a ```protocol::Error```.
+#[derive(Debug)]
+pub enum ServerError {
+    InvalidRequest(String),
+    UnknownStop(Vec<String>),
+    NotFound(String),
+    StaticError(GtfsStaticError),
+    InternalError(String),
+}
+
+impl std::error::Error for ServerError {}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ServerError::*;
+        match self {
+            InvalidRequest(reason) => write!(f, "Invalid request!\n{:}", reason),
+            UnknownStop(stop_ids) => write!(f, "Unknown stop(s) {:}", stop_ids.join(", ")),
+            NotFound(endpoint) => write!(f, "No endpoint \"{:}\"", endpoint),
+            StaticError(e) => write!(f, "Error querying static feed\n{:}", e),
+            InternalError(reason) => write!(f, "Internal server error\n{:}", reason),
+        }
+    }
+}
+
+impl From<GtfsStaticError> for ServerError {
+    fn from(e: GtfsStaticError) -> Self {
+        ServerError::StaticError(e)
+    }
+}
+
+impl From<prost::DecodeError> for ServerError {
+    fn from(e: prost::DecodeError) -> Self {
+        ServerError::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ServerError {
+    fn from(e: serde_json::Error) -> Self {
+        ServerError::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<&ServerError> for protocol::Error {
+    fn from(e: &ServerError) -> Self {
+        let code = match e {
+            ServerError::InvalidRequest(_) => Code::InvalidRequest,
+            ServerError::UnknownStop(_) => Code::UnknownStop,
+            ServerError::NotFound(_) => Code::NotFound,
+            ServerError::StaticError(_) => Code::StaticUnavailable,
+            ServerError::InternalError(_) => Code::Internal,
+        };
+        let mut error = protocol::Error {
+            message: Some(e.to_string()),
+            ..Default::default()
+        };
+        error.set_code(code);
+        error
+    }
+}
+
+/// Encoding of request and response messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    fn decode<T: prost::Message + Default + DeserializeOwned>(
+        self,
+        bytes: &[u8],
+    ) -> Result<T, ServerError> {
+        Ok(match self {
+            Encoding::Protobuf => T::decode(bytes)?,
+            Encoding::Json => serde_json::from_slice(bytes)?,
+        })
+    }
+
+    fn encode<T: prost::Message + Serialize>(self, message: &T) -> Vec<u8> {
+        match self {
+            Encoding::Protobuf => message.encode_to_vec(),
+            Encoding::Json => {
+                serde_json::to_vec(message).expect("protocol messages serialise to JSON")
+            }
+        }
+    }
+
+    /// Encode the ```protocol::Error``` for ```error```.
+    pub fn encode_error(self, error: &ServerError) -> Vec<u8> {
+        self.encode(&protocol::Error::from(error))
+    }
+}
+
+/// Run ```f``` with ```server``` on a thread where blocking is allowed. Static queries block, so
+/// requests are processed off the async runtime threads.
+pub async fn run_blocking<T, F>(server: Arc<Server>, f: F) -> Result<T, ServerError>
+where
+    F: FnOnce(&Server) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(&server))
+        .await
+        .map_err(|e| ServerError::InternalError(e.to_string()))
+}
+
+/// Estimate departures from each new vehicle positions feed published to the server, until the
+/// real-time feeds stop being published.
+pub async fn estimate_departures(server: Arc<Server>) {
+    let mut realtime = server.realtime.clone();
+    let mut observed: Option<Arc<FeedMessage>> = None;
+    loop {
+        let vehicle_positions = realtime.borrow().vehicle_positions.clone();
+        if let Some(vehicle_positions) = vehicle_positions {
+            let new = observed
+                .as_ref()
+                .filter(|observed| Arc::ptr_eq(observed, &vehicle_positions))
+                .is_none();
+            if new {
+                observed = Some(vehicle_positions.clone());
+                let observing = run_blocking(server.clone(), move |server| {
+                    let now = Utc::now().with_timezone(&server.timezone);
+                    server.observe_at(&vehicle_positions, now)
+                });
+                if let Err(e) = observing.await.and_then(|observed| observed) {
+                    eprintln!(
+                        "Unable to estimate departures from vehicle positions\n{:}",
+                        e
+                    );
+                }
+            }
+        }
+        if realtime.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Request processing shared by every client.
+pub struct Server {
+    gtfs: Box<dyn GtfsStatic>,
+    timezone: Tz,
+    realtime: RealtimeReceiver,
+    estimator: Mutex<ArrivalEstimator>,
+    // copied from the estimator, so requests aren't held up while it observes
+    estimates: Mutex<Estimates>,
+}
+
+impl Server {
+    /// Create a server for the static feed ```gtfs```, whose times are local to ```timezone```
+    /// (the agency timezone), using the latest real-time data published to ```realtime``` (see
+    /// ```gtfs_real_time::poller```). Until real-time data is published, scheduled times are
+    /// returned, or times estimated from vehicle positions while ```estimate_departures``` runs.
+    pub fn new(gtfs: Box<dyn GtfsStatic>, timezone: Tz, realtime: RealtimeReceiver) -> Self {
+        let estimator = ArrivalEstimator::new(EstimatorConfig::default());
+        Server {
+            gtfs,
+            timezone,
+            realtime,
+            estimates: Mutex::new(estimator.estimates().clone()),
+            estimator: Mutex::new(estimator),
+        }
+    }
+
+    fn realtime(&self) -> Arc<RealtimeSnapshot> {
+        // cloned so that publishing isn't blocked while a request is processed
+        self.realtime.borrow().clone()
+    }
+
+    /// Update the departure estimates from the ```vehicle_positions``` feed as of ```now```.
+    fn observe_at(
+        &self,
+        vehicle_positions: &FeedMessage,
+        now: DateTime<Tz>,
+    ) -> Result<(), ServerError> {
+        let mut estimator = self.estimator.lock().unwrap_or_else(|e| e.into_inner());
+        estimator.observe(&*self.gtfs, vehicle_positions, now)?;
+        let estimates = estimator.estimates().clone();
+        *self.estimates.lock().unwrap_or_else(|e| e.into_inner()) = estimates;
+        Ok(())
+    }
+
+    /// Decode a request to ```endpoint``` (```departures```, ```closest_vehicle``` or
+    /// ```alerts```), process it and encode the response.
+    pub fn handle(
+        &self,
+        endpoint: &str,
+        encoding: Encoding,
+        request: &[u8],
+    ) -> Result<Vec<u8>, ServerError> {
+        match endpoint {
+            "departures" => Ok(encoding.encode(&self.departures(&encoding.decode(request)?)?)),
+            "closest_vehicle" => {
+                Ok(encoding.encode(&self.closest_vehicle(&encoding.decode(request)?)))
+            }
+            "alerts" => Ok(encoding.encode(&self.alerts(&encoding.decode(request)?))),
+            _ => Err(ServerError::NotFound(String::from(endpoint))),
+        }
+    }
+
+    /// Upcoming services departing from the requested stops.
+    pub fn departures(
+        &self,
+        request: &protocol::DepartureRequest,
+    ) -> Result<protocol::DepartureResponse, ServerError> {
+        self.departures_at(request, Utc::now().with_timezone(&self.timezone))
+    }
+
+    fn departures_at(
+        &self,
+        request: &protocol::DepartureRequest,
+        now: DateTime<Tz>,
+    ) -> Result<protocol::DepartureResponse, ServerError> {
+        if request.stop_id.is_empty() {
+            return Err(ServerError::InvalidRequest(String::from(
+                "No stop_id given",
+            )));
+        }
+        let mut platforms = HashMap::new();
+        for stop_id in request.stop_id.iter() {
+            if let Some(stop) = self.gtfs.stop(stop_id)? {
+                platforms.insert(stop.stop_id, stop.platform_code);
+            }
+        }
+        if platforms.is_empty() {
+            return Err(ServerError::UnknownStop(request.stop_id.clone()));
+        }
+
+        let scheduled = scheduled_departures(
+            &*self.gtfs,
+            &request.stop_id,
+            now - Duration::minutes(LATE_SERVICE_MINUTES),
+            usize::MAX,
+            request.direction_id.map(|direction_id| direction_id as i32),
+        )?;
+        let realtime = self.realtime();
+        let no_trip_updates = FeedMessage::default();
+        // measured from receiving the feed rather than its timestamp, which is in the past when
+        // replaying an archive
+        let stale = now - Duration::minutes(STALE_TRIP_UPDATES_MINUTES);
+        let fresh = realtime
+            .received(FeedType::TripUpdate)
+            .filter(|&received| DateTime::<Utc>::from(received) >= stale)
+            .is_some();
+        let trip_updates = realtime
+            .trip_updates
+            .as_deref()
+            .filter(|_| fresh)
+            .unwrap_or(&no_trip_updates);
+        let departures = predict_departures(&*self.gtfs, scheduled, trip_updates)?;
+        let departures = self
+            .estimates
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .apply(departures, now);
+
+        let fields = request.fields.clone().unwrap_or_default();
+        let vehicle_labels = if fields.vehicle_type() {
+            vehicle_labels(&realtime)
+        } else {
+            HashMap::new()
+        };
+        let mut route_types: HashMap<String, Option<RouteType>> = HashMap::new();
+
+        let mut response = protocol::DepartureResponse {
+            departure: Vec::new(),
+            timestamp: Some(now.timestamp() as u64),
+        };
+        for departure in departures
+            .into_iter()
+            .filter(|departure| departure.expected >= now)
+            .take(request.max_services() as usize)
+        {
+            let scheduled = departure.scheduled;
+            let mut message = protocol::Departure {
+                route: scheduled
+                    .route_short_name
+                    .clone()
+                    .unwrap_or_else(|| scheduled.route_id.clone()),
+                headsign: Some(scheduled.trip_headsign),
+                ..Default::default()
+            };
+            if fields.scheduled_time() {
+                message.scheduled_time = Some(scheduled.departure.timestamp() as u64);
+            }
+            if fields.expected_time() {
+                message.expected_time = Some(departure.expected.timestamp() as u64);
+                message.realtime = Some(departure.time_source != TimeSource::Scheduled);
+                message.estimated = Some(departure.time_source == TimeSource::Estimated);
+            }
+            if fields.vehicle_type() {
+                message.vehicle_type = match vehicle_labels.get(scheduled.trip_id.as_str()) {
+                    Some(label) => Some(String::from(*label)),
+                    None => {
+                        if !route_types.contains_key(&scheduled.route_id) {
+                            let route = self.gtfs.route(&scheduled.route_id)?;
+                            route_types.insert(
+                                scheduled.route_id.clone(),
+                                route.map(|route| route.route_type),
+                            );
+                        }
+                        route_types[&scheduled.route_id].map(route_type_name)
+                    }
+                };
+            }
+            if fields.platform() {
+                message.platform = platforms.get(&scheduled.stop_id).cloned().flatten();
+            }
+            message.set_status(match departure.status {
+                DepartureStatus::Running => Status::Running,
+                DepartureStatus::Canceled => Status::Canceled,
+                DepartureStatus::Skipped => Status::Skipped,
+            });
+            message.stop_id = Some(scheduled.stop_id);
+            response.departure.push(message);
+        }
+        Ok(response)
+    }
+
+    /// Vehicle closest to the requested position.
+    pub fn closest_vehicle(
+        &self,
+        request: &protocol::ClosestVehicleRequest,
+    ) -> protocol::ClosestVehicleResponse {
+        let realtime = self.realtime();
+        let entities = match &realtime.vehicle_positions {
+            Some(feed) => &feed.entity,
+            None => return protocol::ClosestVehicleResponse::default(),
+        };
+
+        let closest = find_closest(entities, request.latitude, request.longitude)
+            .ok()
+            .and_then(|entity| entity.vehicle.as_ref());
+        let vehicle = closest.and_then(|vehicle| {
+            let position = vehicle.position.as_ref()?;
+            let descriptor = vehicle.vehicle.clone().unwrap_or_default();
+            let trip = vehicle.trip.clone().unwrap_or_default();
+            Some(protocol::Vehicle {
+                id: descriptor.id,
+                label: descriptor.label,
+                trip_id: trip.trip_id,
+                route_id: trip.route_id,
+                latitude: position.latitude.into(),
+                longitude: position.longitude.into(),
+                bearing: position.bearing,
+            })
+        });
+        protocol::ClosestVehicleResponse { vehicle }
+    }
+
+    /// Alerts currently in effect, affecting any of the requested stops or routes (or every alert
+    /// if none are requested).
+    pub fn alerts(&self, request: &protocol::AlertsRequest) -> protocol::AlertsResponse {
+        self.alerts_at(request, Utc::now().timestamp() as u64)
+    }
+
+    fn alerts_at(&self, request: &protocol::AlertsRequest, now: u64) -> protocol::AlertsResponse {
+        let realtime = self.realtime();
+        let entities = match &realtime.alerts {
+            Some(feed) => feed.entity.as_slice(),
+            None => &[],
+        };
+        let everything = request.stop_id.is_empty() && request.route_id.is_empty();
+
+        let alert = entities
+            .iter()
+            .filter(|entity| !entity.is_deleted())
+            .filter_map(|entity| entity.alert.as_ref())
+            .filter_map(|alert| {
+                let period = if alert.active_period.is_empty() {
+                    None
+                } else {
+                    Some(alert.active_period.iter().find(|period| {
+                        !matches!(period.start, Some(start) if start > now)
+                            && !matches!(period.end, Some(end) if end < now)
+                    })?)
+                };
+                let affected = everything
+                    || alert.informed_entity.iter().any(|informed| {
+                        let stop_id = informed.stop_id.as_ref();
+                        let route_id = informed.route_id.as_ref();
+                        stop_id.filter(|id| request.stop_id.contains(id)).is_some()
+                            || route_id
+                                .filter(|id| request.route_id.contains(id))
+                                .is_some()
+                    });
+                if !affected {
+                    return None;
+                }
+
+                Some(protocol::Alert {
+                    header: translation(&alert.header_text),
+                    description: translation(&alert.description_text),
+                    start: period.and_then(|period| period.start),
+                    end: period.and_then(|period| period.end),
+                })
+            })
+            .collect();
+        protocol::AlertsResponse { alert }
+    }
+}
+
+/// Labels of the vehicles operating each trip, from the real-time feeds.
+fn vehicle_labels(realtime: &RealtimeSnapshot) -> HashMap<&str, &str> {
+    let mut labels = HashMap::new();
+    let feeds = [&realtime.trip_updates, &realtime.vehicle_positions];
+    for entity in feeds
+        .iter()
+        .filter_map(|feed| feed.as_ref())
+        .flat_map(|feed| &feed.entity)
+    {
+        let (trip, vehicle) = match (&entity.trip_update, &entity.vehicle) {
+            (Some(trip_update), _) => (Some(&trip_update.trip), trip_update.vehicle.as_ref()),
+            (None, Some(vehicle)) => (vehicle.trip.as_ref(), vehicle.vehicle.as_ref()),
+            (None, None) => continue,
+        };
+        let trip_id = trip.and_then(|trip| trip.trip_id.as_deref());
+        let label = vehicle.and_then(|vehicle| vehicle.label.as_deref());
+        if let (Some(trip_id), Some(label)) = (trip_id, label) {
+            labels.insert(trip_id, label);
+        }
+    }
+    labels
+}
+
+/// Name of the type of vehicle used on a route.
+fn route_type_name(route_type: RouteType) -> String {
+    use RouteType::*;
+    String::from(match route_type {
+        Tram => "Tram",
+        Subway => "Subway",
+        Rail => "Train",
+        Bus => "Bus",
+        Ferry => "Ferry",
+        CableTram => "Cable tram",
+        AerialLift => "Aerial lift",
+        Funicular => "Funicular",
+        Trolleybus => "Trolleybus",
+        Monorail => "Monorail",
+        Other(_) => "Other",
+    })
+}
+
+/// English (or otherwise the first) translation of a GTFS-RT string.
+fn translation(text: &Option<TranslatedString>) -> Option<String> {
+    let translations = &text.as_ref()?.translation;
+    let is_english = |translation: &&Translation| match translation.language.as_deref() {
+        Some(language) => language.starts_with("en"),
+        None => true,
+    };
+    translations
+        .iter()
+        .find(is_english)
+        .or_else(|| translations.first())
+        .map(|translation| translation.text.clone())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gtfs::gtfs_real_time::source;
+    use crate::gtfs::gtfs_real_time::trip_update::StopTimeEvent;
+    use crate::gtfs::gtfs_real_time::trip_update::StopTimeUpdate;
+    use crate::gtfs::gtfs_real_time::{poller, GtfsRt, Position, VehicleDescriptor};
+    use crate::gtfs::gtfs_real_time::{
+        Alert, EntitySelector, FeedEntity, FeedHeader, FeedType, TimeRange, TripDescriptor,
+        TripUpdate, VehiclePosition,
+    };
+    use crate::gtfs::gtfs_static::memory::MemoryStatic;
+    use crate::gtfs::gtfs_static::test_feed;
+    use chrono::{NaiveDate, TimeZone};
+    use chrono_tz::Australia::Brisbane;
+    use prost::Message;
+
+    /// Server for the test feed, without real-time data.
+    pub(crate) fn server() -> Server {
+        server_with(RealtimeSnapshot::default())
+    }
+
+    /// Server for the test feed, with the real-time data of ```realtime```.
+    fn server_with(realtime: RealtimeSnapshot) -> Server {
+        Server::new(
+            Box::new(MemoryStatic::from_feed(test_feed::feed())),
+            Brisbane,
+            realtime.into_receiver(),
+        )
+    }
+
+    fn brisbane(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        let time = NaiveDate::from_ymd_opt(2021, 8, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap();
+        Brisbane.from_local_datetime(&time).unwrap()
+    }
+
+    /// Vehicle positions feed with T1 half way from UQ Lakes to Cultural Centre at ```now```.
+    fn t1_position(now: DateTime<Tz>) -> FeedMessage {
+        FeedMessage {
+            entity: vec![FeedEntity {
+                vehicle: Some(VehiclePosition {
+                    trip: Some(TripDescriptor {
+                        trip_id: Some(String::from("T1")),
+                        ..Default::default()
+                    }),
+                    position: Some(Position {
+                        latitude: -27.4840,
+                        longitude: 153.0102,
+                        ..Default::default()
+                    }),
+                    timestamp: Some(now.timestamp() as u64),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn text(text: &str) -> Option<TranslatedString> {
+        Some(TranslatedString {
+            translation: vec![Translation {
+                text: String::from(text),
+                language: None,
+            }],
+        })
+    }
+
+    #[test]
+    fn departures_with_fields() {
+        let mut realtime = RealtimeSnapshot::default();
+        // T1 left UQ Lakes two minutes late, and is running late
+        realtime.update(
+            FeedType::TripUpdate,
+            FeedMessage {
+                entity: vec![FeedEntity {
+                    trip_update: Some(TripUpdate {
+                        trip: TripDescriptor {
+                            trip_id: Some(String::from("T1")),
+                            ..Default::default()
+                        },
+                        stop_time_update: vec![StopTimeUpdate {
+                            stop_sequence: Some(1),
+                            departure: Some(StopTimeEvent {
+                                delay: Some(120),
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        let server = server_with(realtime);
+        let request: protocol::DepartureRequest = serde_json::from_str(
+            r#"{
+                "stop_id": ["10795"],
+                "max_services": 2,
+                "fields": {"expected_time": true, "vehicle_type": true}
+            }"#,
+        )
+        .unwrap();
+
+        // T1 is shown although scheduled to have departed
+        let response = server.departures_at(&request, brisbane(10, 8, 16)).unwrap();
+        let departures = response.departure;
+        assert_eq!(departures.len(), 2);
+        assert_eq!(departures[0].route, "BUZ");
+        assert_eq!(departures[0].headsign.as_deref(), Some("City"));
+        assert_eq!(
+            departures[0].expected_time,
+            Some(brisbane(10, 8, 17).timestamp() as u64)
+        );
+        assert_eq!(departures[0].realtime, Some(true));
+        assert_eq!(departures[0].scheduled_time, None);
+        assert_eq!(departures[0].vehicle_type.as_deref(), Some("Bus"));
+        assert_eq!(departures[1].realtime, Some(false));
+    }
+
+    #[test]
+    fn estimated_without_trip_updates() {
+        let now = brisbane(10, 8, 10);
+        let mut realtime = RealtimeSnapshot::default();
+        // the trip updates stopped ten minutes ago, while T1 is still reporting its position
+        let stopped = now - Duration::minutes(10);
+        realtime.update_at(
+            FeedType::TripUpdate,
+            FeedMessage {
+                header: FeedHeader {
+                    gtfs_realtime_version: String::from("2.0"),
+                    timestamp: Some(stopped.timestamp() as u64),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            stopped.into(),
+        );
+        let server = server_with(realtime);
+        server.observe_at(&t1_position(now), now).unwrap();
+        let request = protocol::DepartureRequest {
+            stop_id: vec![String::from("600029")],
+            fields: Some(protocol::FieldMask {
+                expected_time: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let departures = server.departures_at(&request, now).unwrap().departure;
+        assert_eq!(departures[0].realtime, Some(true));
+        assert_eq!(departures[0].estimated, Some(true));
+        assert!(departures[0].expected_time > Some(brisbane(10, 8, 25).timestamp() as u64));
+        assert_eq!(departures[1].realtime, Some(false));
+        assert_eq!(departures[1].estimated, Some(false));
+    }
+
+    #[test]
+    fn trip_updates_over_estimates() {
+        let now = brisbane(10, 8, 10);
+        let mut realtime = RealtimeSnapshot::default();
+        // an old feed received just now, such as when replaying an archive, is still used
+        realtime.update_at(
+            FeedType::TripUpdate,
+            FeedMessage {
+                header: FeedHeader {
+                    gtfs_realtime_version: String::from("2.0"),
+                    timestamp: Some((now - Duration::days(30)).timestamp() as u64),
+                    ..Default::default()
+                },
+                entity: vec![FeedEntity {
+                    trip_update: Some(TripUpdate {
+                        trip: TripDescriptor {
+                            trip_id: Some(String::from("T1")),
+                            ..Default::default()
+                        },
+                        stop_time_update: vec![StopTimeUpdate {
+                            stop_sequence: Some(3),
+                            departure: Some(StopTimeEvent {
+                                delay: Some(240),
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+            },
+            now.into(),
+        );
+        let server = server_with(realtime);
+        server.observe_at(&t1_position(now), now).unwrap();
+        let request = protocol::DepartureRequest {
+            stop_id: vec![String::from("600029")],
+            fields: Some(protocol::FieldMask {
+                expected_time: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let departures = server.departures_at(&request, now).unwrap().departure;
+        assert_eq!(departures[0].realtime, Some(true));
+        assert_eq!(departures[0].estimated, Some(false));
+        assert_eq!(
+            departures[0].expected_time,
+            Some(brisbane(10, 8, 29).timestamp() as u64)
+        );
+    }
+
+    #[test]
+    fn request_errors() {
+        let server = server();
+        let unknown = protocol::DepartureRequest {
+            stop_id: vec![String::from("missing")],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            server.handle("departures", Encoding::Protobuf, &unknown.encode_to_vec()),
+            Err(ServerError::UnknownStop(_))
+        ));
+        assert!(matches!(
+            server.handle("departures", Encoding::Json, b"{}"),
+            Err(ServerError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            server.handle("departures", Encoding::Json, b"not json"),
+            Err(ServerError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            server.handle("timetable", Encoding::Json, b"{}"),
+            Err(ServerError::NotFound(_))
+        ));
+
+        let error: protocol::Error = serde_json::from_slice(
+            &Encoding::Json.encode_error(&ServerError::UnknownStop(vec![String::from("missing")])),
+        )
+        .unwrap();
+        assert_eq!(error.code(), Code::UnknownStop);
+    }
+
+    #[test]
+    fn alerts_in_effect() {
+        let alert = |header: &str, stop_id: &str, end: u64| FeedEntity {
+            alert: Some(Alert {
+                active_period: vec![TimeRange {
+                    start: None,
+                    end: Some(end),
+                }],
+                informed_entity: vec![EntitySelector {
+                    stop_id: Some(String::from(stop_id)),
+                    ..Default::default()
+                }],
+                header_text: text(header),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut realtime = RealtimeSnapshot::default();
+        realtime.update(
+            FeedType::Alert,
+            FeedMessage {
+                entity: vec![
+                    alert("Lift closed", "600029", 2000),
+                    alert("Stop moved", "1882", 2000),
+                    alert("Expired", "600029", 500),
+                ],
+                ..Default::default()
+            },
+        );
+        let server = server_with(realtime);
+
+        let request = protocol::AlertsRequest {
+            stop_id: vec![String::from("600029")],
+            ..Default::default()
+        };
+        let headers: Vec<Option<String>> = server
+            .alerts_at(&request, 1000)
+            .alert
+            .into_iter()
+            .map(|alert| alert.header)
+            .collect();
+        assert_eq!(headers, vec![Some(String::from("Lift closed"))]);
+
+        let everything = server.alerts_at(&protocol::AlertsRequest::default(), 1000);
+        assert_eq!(everything.alert.len(), 2);
+        assert_eq!(everything.alert[0].end, Some(2000));
+    }
+
+    #[tokio::test]
+    async fn realtime_from_channel() {
+        let vehicle = |id: &str, latitude: f32, longitude: f32| FeedEntity {
+            vehicle: Some(VehiclePosition {
+                vehicle: Some(VehicleDescriptor {
+                    id: Some(String::from(id)),
+                    ..Default::default()
+                }),
+                position: Some(Position {
+                    latitude,
+                    longitude,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (sender, vehicle_positions) = source::channel();
+        let gtfs_rt = GtfsRt::new_optional(None, Some(Box::new(vehicle_positions)), None);
+        let mut realtime = poller::spawn(
+            gtfs_rt,
+            &[(
+                FeedType::VehiclePosition,
+                std::time::Duration::from_millis(10),
+            )],
+        );
+
+        sender.send(FeedMessage {
+            entity: vec![
+                vehicle("Sydney", -33.87, 151.21),
+                vehicle("St Lucia", -27.497, 153.013),
+            ],
+            ..Default::default()
+        });
+        realtime.changed().await.unwrap();
+        let server = Server::new(
+            Box::new(MemoryStatic::from_feed(test_feed::feed())),
+            Brisbane,
+            realtime,
+        );
+
+        let request = protocol::ClosestVehicleRequest {
+            latitude: -27.4975,
+            longitude: 153.0137,
+        };
+        let vehicle = server.closest_vehicle(&request).vehicle.unwrap();
+        assert_eq!(vehicle.id.as_deref(), Some("St Lucia"));
+    }
+}
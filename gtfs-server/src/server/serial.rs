@@ -1 +1,283 @@
 //! Serial communication API to allow for connection and testing over serial instead of HTTP.
+//!
+//! Devices without a network stack (such as the gtfs-locator board) exchange the protocol buffer
+//! messages of the HTTP API over a UART. Each message is sent as a frame
+//!
+//! ```text
+//! | kind (1 byte) | message (protocol buffer) | CRC (2 bytes, big endian) |
+//! ```
+//!
+//! which is COBS encoded and terminated by a zero byte. The kind of a request selects the
+//! endpoint, and its response has the same kind, or ```ERROR``` with a ```protocol::Error```
+//! message if the request failed. The CRC is CRC-16/CCITT-FALSE of the kind and message.
+
+use crate::server::{run_blocking, Encoding, Server, ServerError};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_serial::SerialPortBuilderExt;
+
+/// Frame kind of an ```Error``` response.
+pub const ERROR: u8 = 0;
+/// Frame kind of a ```DepartureRequest``` and its response.
+pub const DEPARTURES: u8 = 1;
+/// Frame kind of a ```ClosestVehicleRequest``` and its response.
+pub const CLOSEST_VEHICLE: u8 = 2;
+/// Frame kind of an ```AlertsRequest``` and its response.
+pub const ALERTS: u8 = 3;
+
+/// Longest (encoded) request accepted, to bound the memory used by a misbehaving device.
+const MAX_FRAME_LENGTH: usize = 4096;
+
+/// Serial associated errors.
+#[derive(Debug)]
+pub enum SerialError {
+    OpenError(String, tokio_serial::Error),
+}
+
+impl std::error::Error for SerialError {}
+
+impl std::fmt::Display for SerialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SerialError::*;
+        match self {
+            OpenError(path, e) => write!(f, "Unable to open serial port {:}\n{:}", path, e),
+        }
+    }
+}
+
+/// Serve requests on each of the serial ports at ```paths```, in background tasks. Fails if any
+/// port cannot be opened.
+pub fn listen(server: Arc<Server>, paths: &[String], baud_rate: u32) -> Result<(), SerialError> {
+    let mut ports = Vec::with_capacity(paths.len());
+    for path in paths {
+        match tokio_serial::new(path, baud_rate).open_native_async() {
+            Ok(port) => ports.push((path.clone(), port)),
+            Err(e) => return Err(SerialError::OpenError(path.clone(), e)),
+        }
+    }
+
+    for (path, port) in ports {
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_port(server, port).await {
+                eprintln!("Serial port {:} closed\n{:}", path, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Serve requests arriving on ```port``` until it is closed.
+pub async fn serve_port<P: AsyncRead + AsyncWrite + Unpin>(
+    server: Arc<Server>,
+    mut port: P,
+) -> std::io::Result<()> {
+    let mut frame = Vec::new();
+    let mut overflowed = false;
+    let mut buffer = [0; 256];
+
+    loop {
+        let read = port.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        for &byte in buffer[..read].iter() {
+            if byte != 0 {
+                if frame.len() < MAX_FRAME_LENGTH {
+                    frame.push(byte);
+                } else {
+                    overflowed = true;
+                }
+                continue;
+            }
+
+            let request = std::mem::take(&mut frame);
+            let response = if overflowed {
+                overflowed = false;
+                let error = ServerError::InvalidRequest(String::from("Frame too long"));
+                encode_frame(ERROR, &Encoding::Protobuf.encode_error(&error))
+            } else if request.is_empty() {
+                // consecutive delimiters, as sent by devices to resynchronise
+                continue;
+            } else {
+                match run_blocking(server.clone(), move |server| process(server, &request)).await {
+                    Ok(response) => response,
+                    Err(error) => encode_frame(ERROR, &Encoding::Protobuf.encode_error(&error)),
+                }
+            };
+            port.write_all(&response).await?;
+        }
+    }
+}
+
+/// Process a received frame (without its delimiter), returning the response frame.
+fn process(server: &Server, frame: &[u8]) -> Vec<u8> {
+    let response = decode_frame(frame).and_then(|(kind, request)| {
+        let endpoint = match kind {
+            DEPARTURES => "departures",
+            CLOSEST_VEHICLE => "closest_vehicle",
+            ALERTS => "alerts",
+            _ => return Err(ServerError::NotFound(format!("frame kind {:}", kind))),
+        };
+        Ok((kind, server.handle(endpoint, Encoding::Protobuf, &request)?))
+    });
+
+    match response {
+        Ok((kind, response)) => encode_frame(kind, &response),
+        Err(e) => encode_frame(ERROR, &Encoding::Protobuf.encode_error(&e)),
+    }
+}
+
+/// Frame ```message``` of ```kind```, including the trailing delimiter.
+pub fn encode_frame(kind: u8, message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(message.len() + 3);
+    data.push(kind);
+    data.extend_from_slice(message);
+    let crc = crc16(&data);
+    data.extend_from_slice(&crc.to_be_bytes());
+
+    let mut frame = cobs_encode(&data);
+    frame.push(0);
+    frame
+}
+
+/// Kind and message of a received frame (without its delimiter).
+pub fn decode_frame(frame: &[u8]) -> Result<(u8, Vec<u8>), ServerError> {
+    let data = cobs_decode(frame)
+        .filter(|data| data.len() >= 3)
+        .ok_or_else(|| ServerError::InvalidRequest(String::from("Malformed frame")))?;
+
+    let (contents, crc) = data.split_at(data.len() - 2);
+    if crc16(contents).to_be_bytes() != crc {
+        return Err(ServerError::InvalidRequest(String::from(
+            "Frame CRC mismatch",
+        )));
+    }
+    Ok((contents[0], contents[1..].to_vec()))
+}
+
+/// Consistent overhead byte stuffing, replacing every zero byte of ```data``` so that zero can
+/// delimit frames.
+fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + data.len() / 254 + 1);
+    let mut code_index = 0;
+    encoded.push(0);
+
+    for &byte in data {
+        if byte != 0 {
+            encoded.push(byte);
+        }
+        let code = encoded.len() - code_index;
+        if byte == 0 || code == 0xFF {
+            encoded[code_index] = code as u8;
+            code_index = encoded.len();
+            encoded.push(0);
+        }
+    }
+    encoded[code_index] = (encoded.len() - code_index) as u8;
+    encoded
+}
+
+/// Reverse ```cobs_encode```, or ```None``` if ```encoded``` is not valid.
+fn cobs_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+
+    while i < encoded.len() {
+        let code = encoded[i] as usize;
+        if code == 0 || i + code > encoded.len() {
+            return None;
+        }
+        data.extend_from_slice(&encoded[i + 1..i + code]);
+        i += code;
+        if code != 0xFF && i < encoded.len() {
+            data.push(0);
+        }
+    }
+    Some(data)
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF).
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::protocol;
+    use crate::server::tests::server;
+    use prost::Message;
+
+    #[test]
+    fn cobs_round_trip() {
+        assert_eq!(
+            cobs_encode(&[0x11, 0x00, 0x22]),
+            vec![0x02, 0x11, 0x02, 0x22]
+        );
+        assert_eq!(cobs_encode(&[0x00]), vec![0x01, 0x01]);
+        assert_eq!(
+            cobs_decode(&[0x02, 0x11, 0x02, 0x22]).unwrap(),
+            vec![0x11, 0x00, 0x22]
+        );
+
+        let long: Vec<u8> = (0..600).map(|i| (i % 256) as u8).collect();
+        let encoded = cobs_encode(&long);
+        assert!(!encoded.contains(&0));
+        assert_eq!(cobs_decode(&encoded).unwrap(), long);
+
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn corrupted_frame() {
+        let mut frame = encode_frame(DEPARTURES, &[0x0A, 0x00, 0x01]);
+        assert_eq!(
+            decode_frame(&frame[..frame.len() - 1]).unwrap(),
+            (DEPARTURES, vec![0x0A, 0x00, 0x01])
+        );
+
+        frame[2] ^= 0x40;
+        assert!(decode_frame(&frame[..frame.len() - 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn request_over_pseudo_terminal() {
+        let (mut device, port) = tokio_serial::SerialStream::pair().unwrap();
+        tokio::spawn(serve_port(Arc::new(server()), port));
+
+        let request = protocol::DepartureRequest {
+            stop_id: vec![String::from("missing")],
+            ..Default::default()
+        };
+        // leading delimiter as sent by a device resynchronising
+        device.write_all(&[0]).await.unwrap();
+        device
+            .write_all(&encode_frame(DEPARTURES, &request.encode_to_vec()))
+            .await
+            .unwrap();
+
+        let mut response = Vec::new();
+        while response.last() != Some(&0) {
+            let mut buffer = [0; 64];
+            let read = device.read(&mut buffer).await.unwrap();
+            response.extend_from_slice(&buffer[..read]);
+        }
+        let (kind, message) = decode_frame(&response[..response.len() - 1]).unwrap();
+        let error = protocol::Error::decode(&message[..]).unwrap();
+        assert_eq!(kind, ERROR);
+        assert_eq!(error.code(), protocol::error::Code::UnknownStop);
+    }
+}